@@ -0,0 +1,134 @@
+//! Lookup throughput of the cache under many concurrent readers while a refresh
+//! keeps replacing entries, compared with the single `Mutex<HashMap>` it replaced.
+
+use std::{
+    collections::HashMap,
+    hint::black_box,
+    sync::{
+        Arc, Barrier, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/cache.rs"]
+mod cache;
+
+use cache::{Cache, CacheEntry, cache_key};
+
+const KEYS: usize = 1_000;
+const MEMBERS: usize = 2_000;
+const READERS: [usize; 3] = [1, 8, 32];
+
+fn entry(group: usize) -> CacheEntry {
+    let values = (0..MEMBERS).map(|member| format!("uid=user{member},ou=users,dc=example,dc=com")).collect();
+    CacheEntry::new(values, vec![format!("cn=group{group},ou=groups,dc=example,dc=com")])
+}
+
+fn keys() -> Vec<String> {
+    (0..KEYS).map(|group| cache_key("/group_members", &format!("group{group}"))).collect()
+}
+
+/// Run `readers` threads doing `iters` lookups each while one writer replaces entries
+/// in a loop, and return the time the readers took
+fn run_readers<R, W>(readers: usize, iters: u64, read: R, write: W) -> Duration
+where
+    R: Fn(usize) + Send + Sync + 'static,
+    W: Fn(usize) + Send + 'static,
+{
+    let read = Arc::new(read);
+    let done = Arc::new(AtomicBool::new(false));
+    let start = Arc::new(Barrier::new(readers + 1));
+
+    let writer = {
+        let done = done.clone();
+        thread::spawn(move || {
+            let mut group = 0;
+            while !done.load(Ordering::Relaxed) {
+                write(group % KEYS);
+                group += 1;
+            }
+        })
+    };
+
+    let handles: Vec<_> = (0..readers)
+        .map(|reader| {
+            let read = read.clone();
+            let start = start.clone();
+            thread::spawn(move || {
+                start.wait();
+                for i in 0..iters as usize {
+                    read((reader * 7919 + i) % KEYS);
+                }
+            })
+        })
+        .collect();
+
+    start.wait();
+    let began = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = began.elapsed();
+
+    done.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+    elapsed
+}
+
+fn concurrent_reads(c: &mut Criterion) {
+    let keys = Arc::new(keys());
+    let entries: Arc<Vec<CacheEntry>> = Arc::new((0..KEYS).map(entry).collect());
+
+    let mut group = c.benchmark_group("reads_during_refresh");
+    for readers in READERS {
+        group.throughput(Throughput::Elements(readers as u64));
+
+        group.bench_with_input(BenchmarkId::new("sharded", readers), &readers, |b, &readers| {
+            let cache = Cache::new();
+            for (key, entry) in keys.iter().zip(entries.iter()) {
+                cache.insert(key.clone(), entry.clone());
+            }
+            b.iter_custom(|iters| {
+                let (read_cache, read_keys) = (cache.clone(), keys.clone());
+                let (write_cache, write_keys, write_entries) = (cache.clone(), keys.clone(), entries.clone());
+                run_readers(readers, iters,
+                    move |i| {
+                        let hit = read_cache.get(&read_keys[i]).unwrap();
+                        hit.record_hit();
+                        black_box(hit.json());
+                    },
+                    move |i| {
+                        write_cache.insert(write_keys[i].clone(), write_entries[i].clone());
+                    })
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("mutex", readers), &readers, |b, &readers| {
+            let cache: Arc<Mutex<HashMap<String, CacheEntry>>> = Arc::new(Mutex::new(
+                keys.iter().cloned().zip(entries.iter().cloned()).collect()));
+            b.iter_custom(|iters| {
+                let (read_cache, read_keys) = (cache.clone(), keys.clone());
+                let (write_cache, write_keys, write_entries) = (cache.clone(), keys.clone(), entries.clone());
+                run_readers(readers, iters,
+                    move |i| {
+                        let cache_guard = read_cache.lock().unwrap();
+                        let hit = &cache_guard[&read_keys[i]];
+                        hit.record_hit();
+                        black_box(hit.json());
+                    },
+                    move |i| {
+                        write_cache.lock().unwrap().insert(write_keys[i].clone(), write_entries[i].clone());
+                    })
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_reads);
+criterion_main!(benches);
@@ -30,6 +30,9 @@ cp -a opt/ldap_cache_daemon/bin/ldap_cache_daemon %{buildroot}/opt/ldap_cache_da
 mkdir -p %{buildroot}/opt/ldap_cache_daemon/etc
 cp -a opt/ldap_cache_daemon/etc/config.yaml %{buildroot}/opt/ldap_cache_daemon/etc/
 
+# State kept across restarts (cache snapshot, change tracking cookie)
+mkdir -p %{buildroot}/opt/ldap_cache_daemon/var
+
 mkdir -p %{buildroot}/etc/sysconfig
 cp -a etc/sysconfig/ldap_cache_daemon %{buildroot}/etc/sysconfig/
 
@@ -41,6 +44,7 @@ chown -R root:root %{buildroot}/opt/ldap_cache_daemon
 chmod 600 %{buildroot}/opt/ldap_cache_daemon/etc/config.yaml
 chmod 755 %{buildroot}/opt/ldap_cache_daemon/bin
 chmod 755 %{buildroot}/opt/ldap_cache_daemon/etc
+chmod 700 %{buildroot}/opt/ldap_cache_daemon/var
 chmod 644 %{buildroot}/etc/sysconfig/ldap_cache_daemon
 chmod 644 %{buildroot}/usr/lib/systemd/system/ldap_cache_daemon.service
 
@@ -48,6 +52,7 @@ chmod 644 %{buildroot}/usr/lib/systemd/system/ldap_cache_daemon.service
 %dir /opt/ldap_cache_daemon
 %dir /opt/ldap_cache_daemon/bin
 %dir /opt/ldap_cache_daemon/etc
+%dir /opt/ldap_cache_daemon/var
 /opt/ldap_cache_daemon/bin/ldap_cache_daemon
 %config(noreplace) /opt/ldap_cache_daemon/etc/config.yaml
 
@@ -0,0 +1,320 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, Request, State},
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AppState,
+    cache::{CacheEntry, cache_key, split_cache_key, unix_time},
+    config::AdminConfig,
+    events::ChangeEvent,
+    listener::serve_unix_socket,
+};
+
+#[derive(Debug, Deserialize)]
+struct InvalidateParams {
+    endpoint: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct InvalidateReport {
+    scope: &'static str,
+    endpoint: Option<String>,
+    name: Option<String>,
+    invalidated: usize,
+}
+
+const DEFAULT_PAGE_LIMIT: usize = 100;
+const MAX_PAGE_LIMIT: usize = 10000;
+
+#[derive(Debug, Deserialize)]
+struct CacheListParams {
+    endpoint: Option<String>,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventListParams {
+    #[serde(default)]
+    since: u64,
+    endpoint: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct EventList {
+    events: Vec<ChangeEvent>,
+}
+
+#[derive(Debug, Serialize)]
+struct RefreshInfo {
+    at: u64,
+    ok: bool,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CacheEntryInfo {
+    key: String,
+    endpoint: String,
+    name: String,
+    size: usize,
+    bytes: usize,
+    fetched_at: u64,
+    last_refresh: Option<RefreshInfo>,
+    hits: u64,
+    last_access: Option<u64>,
+    stale: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    values: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dns: Option<Vec<String>>,
+}
+
+impl CacheEntryInfo {
+    fn new(key: &str, entry: &CacheEntry, with_data: bool) -> Self {
+        let (endpoint, name) = split_cache_key(key).unwrap_or((key, ""));
+        CacheEntryInfo {
+            key: key.to_string(),
+            endpoint: endpoint.to_string(),
+            name: name.to_string(),
+            size: entry.values.len(),
+            bytes: entry.size_bytes(),
+            fetched_at: unix_time(entry.fetched_at),
+            last_refresh: entry.last_refresh.as_ref().map(|outcome| RefreshInfo {
+                at: unix_time(outcome.at),
+                ok: outcome.error.is_none(),
+                error: outcome.error.clone(),
+            }),
+            hits: entry.hits(),
+            last_access: entry.last_access().map(unix_time),
+            stale: entry.stale,
+            values: with_data.then(|| entry.values.clone()),
+            dns: with_data.then(|| entry.dns.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CachePage {
+    total: usize,
+    offset: usize,
+    limit: usize,
+    entries: Vec<CacheEntryInfo>,
+}
+
+pub async fn start_admin_server(admin: AdminConfig, app_state: Arc<AppState>) -> Result<(), Box<dyn std::error::Error>> {
+    let app = Router::new()
+        .route("/invalidate", post(invalidate_handler))
+        .route("/refresh", post(refresh_handler))
+        .route("/cache", get(cache_list_handler))
+        .route("/cache/dump", get(cache_dump_handler))
+        .route("/events", get(events_handler))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), require_admin_token))
+        .with_state(app_state);
+
+    if let Some(socket) = admin.socket() {
+        info!("Starting admin server on unix:{}", socket);
+        serve_unix_socket(socket, app).await?;
+    } else if let Some(bind_addr) = admin.bind_addr() {
+        info!("Starting admin server on {}", bind_addr);
+        let listener = tokio::net::TcpListener::bind(bind_addr).await?;
+        axum::serve(listener, app).await?;
+    }
+
+    Ok(())
+}
+
+/// Compare tokens without returning early on the first mismatching byte
+pub fn tokens_match(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given.iter().zip(expected).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Check whether a request carries the admin token as its bearer token
+fn has_admin_token(admin: &AdminConfig, headers: &HeaderMap) -> bool {
+    headers.get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| tokens_match(token.as_bytes(), admin.token().as_bytes()))
+}
+
+async fn require_admin_token(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(admin) = state.config.admin() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if !has_admin_token(admin, request.headers()) {
+        warn!("Rejected unauthenticated admin request for {}", request.uri().path());
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+/// Drop one cached key (`endpoint` and `name`), every key of one endpoint (`endpoint`),
+/// or the whole cache (no parameters)
+async fn invalidate_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<InvalidateParams>,
+) -> Result<Json<InvalidateReport>, (StatusCode, String)> {
+    let InvalidateParams { endpoint, name } = params;
+
+    if let Some(endpoint_path) = &endpoint
+        && !state.config.endpoints().iter().any(|ep| ep.path() == endpoint_path)
+    {
+        return Err((StatusCode::NOT_FOUND, format!("No endpoint configured for {}", endpoint_path)));
+    }
+
+    let cache = &state.cache;
+    let (scope, invalidated) = match (&endpoint, &name) {
+        (None, None) => {
+            let invalidated = cache.len();
+            cache.clear();
+            ("all", invalidated)
+        }
+        (Some(endpoint_path), None) => {
+            let mut invalidated = 0;
+            cache.retain(|key, _| {
+                let keep = split_cache_key(key).is_none_or(|(path, _)| path != endpoint_path);
+                invalidated += usize::from(!keep);
+                keep
+            });
+            ("endpoint", invalidated)
+        }
+        (Some(endpoint_path), Some(name)) => {
+            let removed = cache.remove(&cache_key(endpoint_path, name));
+            ("key", usize::from(removed.is_some()))
+        }
+        (None, Some(_)) => {
+            return Err((StatusCode::BAD_REQUEST, "name requires endpoint".to_string()));
+        }
+    };
+
+    info!("Admin invalidated {} cached entries (scope: {})", invalidated, scope);
+    state.metrics.cache_evictions.with_label_values(&["invalidate"]).inc_by(invalidated as u64);
+
+    Ok(Json(InvalidateReport { scope, endpoint, name, invalidated }))
+}
+
+async fn refresh_handler(State(state): State<Arc<AppState>>) -> (StatusCode, Json<crate::RefreshSummary>) {
+    info!("Admin triggered cache refresh");
+    let summary = crate::refresh_cache(state.clone()).await;
+
+    let status = if summary.error.is_some() { StatusCode::BAD_GATEWAY } else { StatusCode::OK };
+    (status, Json(summary))
+}
+
+/// Build one page of cache entries ordered by key, optionally restricted to one endpoint
+fn cache_page(state: &AppState, params: CacheListParams, with_data: bool) -> CachePage {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+
+    let mut entries = state.cache.entries();
+    entries.retain(|(key, _)| match &params.endpoint {
+        Some(endpoint_path) => split_cache_key(key).is_some_and(|(path, _)| path == endpoint_path),
+        None => true,
+    });
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let total = entries.len();
+
+    let entries = entries.iter()
+        .skip(params.offset)
+        .take(limit)
+        .map(|(key, entry)| CacheEntryInfo::new(key, entry, with_data))
+        .collect();
+
+    CachePage {
+        total,
+        offset: params.offset,
+        limit,
+        entries,
+    }
+}
+
+/// List cached keys with their statistics
+async fn cache_list_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<CacheListParams>,
+) -> Json<CachePage> {
+    Json(cache_page(&state, params, false))
+}
+
+/// Like the listing, but including the cached values and the DNs they were built from
+async fn cache_dump_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<CacheListParams>,
+) -> Json<CachePage> {
+    Json(cache_page(&state, params, true))
+}
+
+/// Recent change events, oldest first, with an id greater than `since`
+async fn events_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<EventListParams>,
+) -> Json<EventList> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+    Json(EventList {
+        events: state.events.recent(params.since, params.endpoint.as_deref(), limit),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match(b"s3cret-token", b"s3cret-token"));
+        assert!(!tokens_match(b"s3cret-tokem", b"s3cret-token"));
+        assert!(!tokens_match(b"s3cret", b"s3cret-token"));
+        assert!(!tokens_match(b"", b"s3cret-token"));
+    }
+
+    #[test]
+    fn test_cache_page() {
+        let config = serde_yaml::from_str(r#"
+ldap:
+  url: "ldap://localhost:389"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={})"
+    search_scope: "subtree"
+    attribute: "member"
+"#).unwrap();
+        let state = AppState::new(Arc::new(config), Default::default(), Arc::new(crate::events::EventLog::new(10, None)));
+        for name in ["c", "a", "b"] {
+            state.cache.insert(cache_key("/group_members", name), CacheEntry::new(vec![name.repeat(3)], vec![]));
+        }
+        state.cache.insert(cache_key("/user_maildrop", "a"), CacheEntry::new(vec![], vec![]));
+
+        let params = CacheListParams { endpoint: Some("/group_members".to_string()), offset: 1, limit: Some(1) };
+        let page = cache_page(&state, params, false);
+        assert_eq!(page.total, 3);
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].name, "b");
+        assert_eq!(page.entries[0].bytes, 3);
+        assert!(page.entries[0].values.is_none());
+
+        let params = CacheListParams { endpoint: None, offset: 0, limit: None };
+        let page = cache_page(&state, params, true);
+        assert_eq!(page.total, 4);
+        assert_eq!(page.limit, DEFAULT_PAGE_LIMIT);
+        assert_eq!(page.entries[0].values, Some(vec!["aaa".to_string()]));
+    }
+}
@@ -0,0 +1,288 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::warn;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    admin::tokens_match,
+    config::{ApiClientConfig, Config, EndpointConfig},
+    listener::Peer,
+};
+
+/// Who a request comes from
+#[derive(Clone, Debug, PartialEq)]
+enum Identity<'a> {
+    /// A configured client, by name
+    Client(&'a str),
+    /// No credentials that identify a client
+    Anonymous,
+    /// A bearer token that matches no client
+    InvalidToken,
+}
+
+/// Split on every `separator` that is not escaped with a backslash
+fn split_unescaped(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == separator => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => (),
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// The RDNs of a distinguished name, normalized for comparison: `CN = mail, O = Example`
+/// and `cn=mail,o=example` are the same subject, and so are `CN=a+UID=b` and `UID=b+CN=a`
+fn parse_dn(dn: &str) -> Vec<String> {
+    split_unescaped(dn, ',')
+        .into_iter()
+        .map(|rdn| {
+            let mut attributes: Vec<String> = split_unescaped(rdn, '+')
+                .into_iter()
+                .map(|attribute| match attribute.split_once('=') {
+                    Some((name, value)) => format!("{}={}", name.trim(), value.trim()),
+                    None => attribute.trim().to_string(),
+                })
+                .map(|attribute| attribute.to_lowercase())
+                .collect();
+            attributes.sort();
+            attributes.join("+")
+        })
+        .collect()
+}
+
+/// Whether a certificate subject is the configured one. Certificates list their RDNs from
+/// the root down (`O=Example, CN=mail`) while RFC 4514 writes them from the leaf up
+/// (`CN=mail,O=Example`), so both orders match.
+fn same_dn(subject: &str, expected: &str) -> bool {
+    let subject = parse_dn(subject);
+    let mut expected = parse_dn(expected);
+    if subject == expected {
+        return true;
+    }
+    expected.reverse();
+    subject == expected
+}
+
+/// Identify the client of a request by its bearer token, then by its TLS client
+/// certificate, then by the uid of the process on the other end of a unix socket
+fn authenticate<'a>(clients: &'a [ApiClientConfig], headers: &HeaderMap, peer: &Peer) -> Identity<'a> {
+    let token = headers.get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = token {
+        let hash = hex::encode(Sha256::digest(token.as_bytes()));
+        // Check every client so the time taken does not tell which one came close
+        let matched = clients.iter().fold(None, |matched, client| {
+            let matches = client.token_sha256().as_ref()
+                .is_some_and(|expected| tokens_match(hash.as_bytes(), expected.to_lowercase().as_bytes()));
+            matched.or(matches.then_some(client))
+        });
+        return match matched {
+            Some(client) => Identity::Client(client.name()),
+            None => Identity::InvalidToken,
+        };
+    }
+
+    if let Some(subject) = &peer.tls_subject
+        && let Some(client) = clients.iter()
+            .find(|client| client.tls_subject().as_ref().is_some_and(|expected| same_dn(subject, expected)))
+    {
+        return Identity::Client(client.name());
+    }
+
+    if let Some(uid) = peer.uid
+        && let Some(client) = clients.iter().find(|client| *client.unix_uid() == Some(uid))
+    {
+        return Identity::Client(client.name());
+    }
+
+    Identity::Anonymous
+}
+
+/// The configured client a request comes from, if any
+pub fn authenticated_client<'a>(clients: &'a [ApiClientConfig], headers: &HeaderMap, peer: &Peer) -> Option<&'a ApiClientConfig> {
+    match authenticate(clients, headers, peer) {
+        Identity::Client(name) => clients.iter().find(|client| client.name() == name),
+        _ => None,
+    }
+}
+
+/// Describe the other end of a connection for log messages
+fn describe(peer: &Peer) -> String {
+    match (peer.addr, peer.uid) {
+        (Some(addr), _) => addr.to_string(),
+        (None, Some(uid)) => format!("uid {}", uid),
+        (None, None) => "unknown peer".to_string(),
+    }
+}
+
+/// Who may query a restricted endpoint
+#[derive(Clone)]
+pub struct EndpointAccess {
+    config: Arc<Config>,
+    path: String,
+    allow: Vec<String>,
+}
+
+impl EndpointAccess {
+    /// Access rules of an endpoint, `None` if it is open to every client
+    pub fn new(config: Arc<Config>, endpoint: &EndpointConfig) -> Option<Self> {
+        let allow = endpoint.allow().clone()?;
+        Some(EndpointAccess { path: endpoint.path().clone(), allow, config })
+    }
+
+    pub fn allowed(&self) -> &[String] {
+        &self.allow
+    }
+}
+
+/// Reject requests to a restricted endpoint unless they come from a client on its allow
+/// list: 401 when the client cannot be identified, 403 when it is not allowed
+pub async fn require_access(State(access): State<EndpointAccess>, request: Request, next: Next) -> Response {
+    let peer = request.extensions().get::<Peer>().cloned().unwrap_or_default();
+
+    match authenticate(access.config.clients(), request.headers(), &peer) {
+        Identity::Client(name) if access.allow.iter().any(|allowed| allowed == name) => next.run(request).await,
+        Identity::Client(name) => {
+            warn!("Denied client {} ({}) access to {}", name, describe(&peer), access.path);
+            StatusCode::FORBIDDEN.into_response()
+        }
+        identity => {
+            let reason = if identity == Identity::InvalidToken { "an invalid token" } else { "no credentials" };
+            warn!("Rejected request from {} with {} for {}", describe(&peer), reason, access.path);
+            (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"))]).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clients() -> Vec<ApiClientConfig> {
+        serde_yaml::from_str(&format!(r#"
+- name: "mail"
+  tls_subject: "CN = mail.example.com"
+  unix_uid: 8
+- name: "portal"
+  token_sha256: "{}"
+"#, hex::encode(Sha256::digest(b"portal-token")))).unwrap()
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let clients = clients();
+        let no_headers = HeaderMap::new();
+        let anonymous = Peer::default();
+
+        assert_eq!(authenticate(&clients, &bearer("portal-token"), &anonymous), Identity::Client("portal"));
+        assert_eq!(authenticate(&clients, &bearer("guessed"), &anonymous), Identity::InvalidToken);
+        assert_eq!(authenticate(&clients, &no_headers, &anonymous), Identity::Anonymous);
+
+        let tls = Peer { tls_subject: Some("CN=mail.example.com".to_string()), ..Default::default() };
+        assert_eq!(authenticate(&clients, &no_headers, &tls), Identity::Client("mail"));
+        // A token that does not match is rejected even with a valid certificate
+        assert_eq!(authenticate(&clients, &bearer("guessed"), &tls), Identity::InvalidToken);
+
+        let other = Peer { tls_subject: Some("CN=web.example.com".to_string()), ..Default::default() };
+        assert_eq!(authenticate(&clients, &no_headers, &other), Identity::Anonymous);
+
+        let unix = Peer { uid: Some(8), ..Default::default() };
+        assert_eq!(authenticate(&clients, &no_headers, &unix), Identity::Client("mail"));
+        let root = Peer { uid: Some(0), ..Default::default() };
+        assert_eq!(authenticate(&clients, &no_headers, &root), Identity::Anonymous);
+    }
+
+    #[test]
+    fn test_same_dn() {
+        assert!(same_dn("CN = mail.example.com, O = Example", "cn=mail.example.com,o=example"));
+        // Certificates print the root first, operators write the leaf first
+        assert!(same_dn("C=DE, O=Example, CN=mail.example.com", "CN=mail.example.com,O=Example,C=DE"));
+        assert!(same_dn("CN=mail+UID=8, O=Example", "O=Example, UID=8+CN=mail"));
+        assert!(same_dn("CN=Smith\\, Bob, O=Example", "o=example,cn=smith\\, bob"));
+
+        assert!(!same_dn("CN=mail.example.com, O=Example", "CN=mail.example.com"));
+        assert!(!same_dn("CN=Smith\\, Bob, O=Example", "CN=Smith, CN=Bob, O=Example"));
+        assert!(!same_dn("O=Example, OU=Mail, CN=mail", "O=Example, CN=mail, OU=Mail"));
+    }
+
+    #[tokio::test]
+    async fn test_require_access() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        use crate::handler::tests::{get, test_app};
+
+        let app = test_app(&format!(r#"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+clients:
+  - name: "mail"
+    token_sha256: "{}"
+  - name: "portal"
+    token_sha256: "{}"
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={{}})"
+    search_scope: "subtree"
+    attribute: "member"
+    allow: ["mail"]
+  - path: "/user_maildrop"
+    search_base: "ou=users,dc=example,dc=com"
+    search_filter: "(uid={{}})"
+    search_scope: "subtree"
+    attribute: "mail"
+"#, hex::encode(Sha256::digest(b"mail-token")), hex::encode(Sha256::digest(b"portal-token"))),
+            &[("/group_members", "staff", "alice"), ("/user_maildrop", "alice", "alice@example.com")]);
+
+        let with_token = |mut request: Request, token: Option<&str>| {
+            if let Some(token) = token {
+                request.headers_mut().insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            }
+            request
+        };
+        let batch = || Request::post("/group_members/_batch")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"["staff"]"#))
+            .unwrap();
+
+        for request in [|| get("/group_members/staff"), batch, || get("/group_members/staff/watch")] {
+            for token in [None, Some("guessed")] {
+                let response = app.clone().oneshot(with_token(request(), token)).await.unwrap();
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+                assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+            }
+            let response = app.clone().oneshot(with_token(request(), Some("portal-token"))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let response = app.clone().oneshot(with_token(request(), Some("mail-token"))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        // Endpoints without an allow list stay open
+        assert_eq!(app.oneshot(get("/user_maildrop/alice")).await.unwrap().status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use axum::{
+    Extension, Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures::{StreamExt, stream};
+use log::{error, info};
+use serde::Serialize;
+
+use crate::{
+    AppState,
+    cache::cache_key,
+    config::EndpointConfig,
+    handler::{execute_ldap_query, store},
+    ldap::connect_and_bind,
+    mirror::current_index,
+};
+
+/// Outcome of one name in a batch lookup
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum BatchResult {
+    Values { values: Vec<String> },
+    Error { error: String },
+}
+
+/// Look up many names of one endpoint at once. Cached names are answered from the cache,
+/// the others are fetched with bounded concurrency over a single LDAP connection shared
+/// by their queries and cached like any other lookup.
+///
+/// The response maps every distinct name to its values, or to the error that prevented
+/// looking it up, so one failing name does not fail the whole batch.
+pub async fn batch_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(endpoint): Extension<Arc<EndpointConfig>>,
+    Json(names): Json<Vec<String>>,
+) -> Response {
+    let AppState { config, cache, mirrors, limits, metrics, .. } = &*state;
+    let endpoint: &EndpointConfig = &endpoint;
+
+    let batch = config.server().batch().clone().unwrap_or_default();
+    let names: BTreeSet<String> = names.into_iter().collect();
+    if names.len() > *batch.max_names() {
+        return (StatusCode::PAYLOAD_TOO_LARGE,
+            format!("batch of {} names exceeds the limit of {}", names.len(), batch.max_names())).into_response();
+    }
+
+    info!("Batch lookup of {} names on endpoint '{}'", names.len(), endpoint.path());
+
+    let mut results = BTreeMap::new();
+
+    // Mirrored endpoints answer every name from their local copy once it has been built
+    if let Some(index) = current_index(mirrors, endpoint.path()) {
+        for name in names {
+            let values = index.get(&name).cloned().unwrap_or_default();
+            results.insert(name, BatchResult::Values { values });
+        }
+        return Json(results).into_response();
+    }
+
+    let mut misses = vec![];
+    for name in names {
+        match cache.get(&cache_key(endpoint.path(), &name)) {
+            Some(cached) => {
+                cached.record_hit();
+                metrics.cache_hits.with_label_values(&[endpoint.path()]).inc();
+                results.insert(name, BatchResult::Values { values: cached.values.clone() });
+            }
+            None => misses.push(name),
+        }
+    }
+
+    if !misses.is_empty() {
+        metrics.cache_misses.with_label_values(&[endpoint.path()]).inc_by(misses.len() as u64);
+        info!("Batch lookup on '{}': {} cache hits, querying LDAP for {} misses",
+            endpoint.path(), results.len(), misses.len());
+
+        // The whole batch shares one connection, so it takes a single turn
+        let _permit = match limits.acquire_miss().await {
+            Ok(permit) => permit,
+            Err(busy) => return busy.into_response(),
+        };
+        match connect_and_bind(config.ldap(), metrics).await {
+            Ok(ldap) => {
+                let fetched: Vec<_> = stream::iter(misses)
+                    .map(|name| {
+                        // Clones of the handle multiplex their operations over the same connection
+                        let mut ldap = ldap.clone();
+                        async move {
+                            let result = execute_ldap_query(&mut ldap, metrics, endpoint, &name)
+                                .await
+                                .map_err(|e| format!("Failed to execute LDAP query: {}", e));
+                            (name, result)
+                        }
+                    })
+                    .buffer_unordered(*batch.concurrency())
+                    .collect()
+                    .await;
+
+                for (name, result) in fetched {
+                    let result = match result {
+                        Ok(entry) => BatchResult::Values { values: store(cache, endpoint, &name, entry).values.clone() },
+                        Err(error) => {
+                            error!("Batch lookup of '{}' on '{}' failed: {}", name, endpoint.path(), error);
+                            BatchResult::Error { error }
+                        }
+                    };
+                    results.insert(name, result);
+                }
+            }
+            Err(e) => {
+                let error = format!("LDAP connect/bind failed: {}", e);
+                error!("Batch lookup on '{}' failed: {}", endpoint.path(), error);
+                for name in misses {
+                    results.insert(name, BatchResult::Error { error: error.clone() });
+                }
+            }
+        }
+    }
+
+    Json(results).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::CacheEntry, config::Config, events::EventLog};
+
+    fn state() -> Arc<AppState> {
+        // Nothing listens on port 1, so every LDAP lookup fails right away
+        let config: Config = serde_yaml::from_str(r#"
+ldap:
+  url: "ldap://127.0.0.1:1"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+  batch:
+    max_names: 3
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={})"
+    search_scope: "subtree"
+    attribute: "member"
+"#).unwrap();
+        Arc::new(AppState::new(Arc::new(config), Default::default(), Arc::new(EventLog::new(10, None))))
+    }
+
+    async fn lookup(state: &Arc<AppState>, names: &[&str]) -> (StatusCode, serde_json::Value) {
+        let names = names.iter().map(|name| name.to_string()).collect();
+        let endpoint = Arc::new(state.config.endpoints()[0].clone());
+        let response = batch_handler(State(state.clone()), Extension(endpoint), Json(names)).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_batch_lookup() {
+        let state = state();
+        let staff = state.cache.insert(cache_key("/group_members", "staff"), CacheEntry::new(vec!["alice".to_string()], vec![]));
+
+        let (status, results) = lookup(&state, &["staff", "admins", "staff"]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(results["staff"], serde_json::json!({ "values": ["alice"] }));
+        assert!(results["admins"]["error"].as_str().unwrap().starts_with("LDAP connect/bind failed"));
+        assert_eq!(results.as_object().unwrap().len(), 2);
+        assert_eq!(staff.hits(), 1);
+
+        let (status, _) = lookup(&state, &["a", "b", "c", "d"]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,467 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use dashmap::{DashMap, Entry};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast;
+
+/// Number of changes kept for subscribers that fall behind
+const CHANGE_CAPACITY: usize = 4096;
+
+/// Called with every change to the cache as it is made
+type Recorder = Arc<dyn Fn(&CacheChange) + Send + Sync>;
+
+/// Shared cache of lookup results, keyed by `"{endpoint_path}:{name}"`.
+///
+/// The map is sharded so readers and refresh writers only contend on the shard of the
+/// key they touch, and entries are reference counted so a hit is a pointer clone.
+/// Entries are never modified in place except for their access statistics; a refresh
+/// replaces the whole entry.
+///
+/// Adding an entry, or replacing one with different values, publishes a `CacheChange` to
+/// subscribers. Subscribers that fall behind miss changes; a recorder, if any, is called
+/// with every change as it is made. Adding a key that was not cached is marked as a first
+/// fill, since there were no values to compare with.
+#[derive(Clone)]
+pub struct Cache {
+    entries: Arc<DashMap<String, Arc<CacheEntry>>>,
+    changes: broadcast::Sender<Arc<CacheChange>>,
+    next_version: Arc<AtomicU64>,
+    recorder: Option<Recorder>,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            entries: Arc::default(),
+            changes: broadcast::channel(CHANGE_CAPACITY).0,
+            // Starting from the current time keeps versions increasing across restarts
+            next_version: Arc::new(AtomicU64::new(unix_time_ms(SystemTime::now()))),
+            recorder: None,
+        }
+    }
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Cache::default()
+    }
+
+    /// A cache that calls `recorder` with every change before publishing it. The recorder
+    /// runs after the key is unlocked, so changes to one key made at the same time may reach
+    /// it out of order; the version of their entry tells which came last.
+    pub fn with_recorder(recorder: impl Fn(&CacheChange) + Send + Sync + 'static) -> Self {
+        Cache { recorder: Some(Arc::new(recorder)), ..Cache::default() }
+    }
+
+    /// Receive every change to the values of a cached entry from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<CacheChange>> {
+        self.changes.subscribe()
+    }
+
+    pub fn get(&self, cache_key: &str) -> Option<Arc<CacheEntry>> {
+        self.entries.get(cache_key).map(|entry| entry.value().clone())
+    }
+
+    /// Add or replace an entry. The entry gets a new version unless it replaces one with
+    /// the same values, in which case it keeps the version and modification time of the
+    /// entry it replaces.
+    pub fn insert(&self, cache_key: String, mut entry: CacheEntry) -> Arc<CacheEntry> {
+        let slot = self.entries.entry(cache_key.clone());
+        let previous = match &slot {
+            Entry::Occupied(occupied) => Some(occupied.get().clone()),
+            Entry::Vacant(_) => None,
+        };
+        match &previous {
+            Some(previous) if previous.values == entry.values => {
+                entry.version = previous.version;
+                entry.modified_at = previous.modified_at;
+            }
+            _ => entry.version = self.next_version.fetch_add(1, Ordering::Relaxed),
+        }
+        let entry = Arc::new(entry);
+        // The shard is unlocked again before the change is recorded, which may write to disk
+        slot.insert(entry.clone());
+
+        let (added, removed) = match &previous {
+            Some(previous) if previous.version == entry.version => return entry,
+            Some(previous) => diff_values(&previous.values, &entry.values),
+            None => (vec![], vec![]),
+        };
+        let change = Arc::new(CacheChange {
+            key: cache_key,
+            at: SystemTime::now(),
+            added,
+            removed,
+            first_fill: previous.is_none(),
+            entry: entry.clone(),
+        });
+        if let Some(recorder) = &self.recorder {
+            recorder(&change);
+        }
+
+        // Sending only fails when nobody is subscribed
+        let _ = self.changes.send(change);
+        entry
+    }
+
+    pub fn remove(&self, cache_key: &str) -> Option<Arc<CacheEntry>> {
+        self.entries.remove(cache_key).map(|(_, entry)| entry)
+    }
+
+    pub fn contains_key(&self, cache_key: &str) -> bool {
+        self.entries.contains_key(cache_key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&self) {
+        self.entries.clear();
+    }
+
+    /// Keep only the entries for which `keep` returns true
+    pub fn retain(&self, mut keep: impl FnMut(&str, &CacheEntry) -> bool) {
+        self.entries.retain(|key, entry| keep(key, entry));
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.entries.iter().map(|entry| entry.key().clone()).collect()
+    }
+
+    /// Point-in-time copy of every key and entry. Entries are shared, not cloned.
+    pub fn entries(&self) -> Vec<(String, Arc<CacheEntry>)> {
+        self.entries.iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+}
+
+/// A cached lookup result along with the DNs of the LDAP entries it was built from,
+/// so change notifications can find the entries they affect.
+///
+/// Response bodies are rendered once per format and kept with the entry, so `values` must
+/// not be changed afterwards; build a new entry instead.
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub values: Vec<String>,
+    pub dns: Vec<String>,
+    /// Bodies indexed by `Format`, JSON is rendered up front and the others on first use
+    bodies: [OnceLock<Bytes>; Format::ALL.len()],
+    content_hash: String,
+    pub fetched_at: SystemTime,
+    /// When the values last changed, kept by the cache across refreshes that return the same values
+    pub modified_at: SystemTime,
+    pub last_refresh: Option<RefreshOutcome>,
+    /// Loaded from a snapshot and not yet confirmed by a refresh
+    pub stale: bool,
+    /// Assigned by the cache, increases whenever the values of an entry change
+    pub version: u64,
+    hits: AtomicU64,
+    /// Unix time in milliseconds, 0 if never accessed
+    last_access_ms: AtomicU64,
+}
+
+/// An entry was added to the cache, or its values were replaced with different ones
+#[derive(Debug)]
+pub struct CacheChange {
+    pub key: String,
+    pub at: SystemTime,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// The key was not cached before, by a miss, a preload, a snapshot or a fetch after it
+    /// was removed. Nothing changed, `added` and `removed` are empty.
+    pub first_fill: bool,
+    /// The entry now in the cache
+    pub entry: Arc<CacheEntry>,
+}
+
+/// Result of the most recent background refresh of an entry
+#[derive(Clone, Debug)]
+pub struct RefreshOutcome {
+    pub at: SystemTime,
+    pub error: Option<String>,
+}
+
+impl CacheEntry {
+    pub fn new(values: Vec<String>, dns: Vec<String>) -> Self {
+        let dns = dns.iter().map(|dn| normalize_dn(dn)).collect();
+        let json = render_json(&values);
+        let content_hash = content_hash(&json);
+        let bodies: [OnceLock<Bytes>; Format::ALL.len()] = Default::default();
+        bodies[Format::Json as usize].set(json).unwrap();
+        let now = SystemTime::now();
+        CacheEntry {
+            values,
+            dns,
+            bodies,
+            content_hash,
+            fetched_at: now,
+            modified_at: now,
+            last_refresh: None,
+            stale: false,
+            version: 0,
+            hits: AtomicU64::new(0),
+            last_access_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// The values rendered in a format, shared rather than copied
+    pub fn body(&self, format: Format) -> Bytes {
+        self.bodies[format as usize].get_or_init(|| format.render(&self.values)).clone()
+    }
+
+    /// The values rendered as a JSON array, shared rather than copied
+    pub fn json(&self) -> Bytes {
+        self.body(Format::Json)
+    }
+
+    /// Hash of the rendered values, identical for entries with the same values
+    pub fn content_hash(&self) -> &str {
+        &self.content_hash
+    }
+
+    pub fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        self.record_access();
+    }
+
+    pub fn record_access(&self) {
+        self.last_access_ms.store(unix_time_ms(SystemTime::now()), Ordering::Relaxed);
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn last_access(&self) -> Option<SystemTime> {
+        match self.last_access_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+        }
+    }
+
+    /// Keep the access statistics of the entry this one replaces
+    pub fn carry_over_stats(&mut self, previous: &CacheEntry) {
+        *self.hits.get_mut() = previous.hits();
+        *self.last_access_ms.get_mut() = previous.last_access_ms.load(Ordering::Relaxed);
+    }
+
+    /// Approximate size of the cached values in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.values.iter().map(|value| value.len()).sum()
+    }
+}
+
+impl Clone for CacheEntry {
+    fn clone(&self) -> Self {
+        CacheEntry {
+            values: self.values.clone(),
+            dns: self.dns.clone(),
+            bodies: self.bodies.clone(),
+            content_hash: self.content_hash.clone(),
+            fetched_at: self.fetched_at,
+            modified_at: self.modified_at,
+            last_refresh: self.last_refresh.clone(),
+            stale: self.stale,
+            version: self.version,
+            hits: AtomicU64::new(self.hits()),
+            last_access_ms: AtomicU64::new(self.last_access_ms.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Values present only in `new` and values present only in `old`, each in their original order
+pub fn diff_values(old: &[String], new: &[String]) -> (Vec<String>, Vec<String>) {
+    let old_set: HashSet<&String> = old.iter().collect();
+    let new_set: HashSet<&String> = new.iter().collect();
+
+    let added = new.iter().filter(|value| !old_set.contains(value)).cloned().collect();
+    let removed = old.iter().filter(|value| !new_set.contains(value)).cloned().collect();
+    (added, removed)
+}
+
+/// Representation of the values in a response
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// JSON array
+    #[default]
+    Json,
+    /// One value per line
+    Text,
+    /// One value per record, quoted where needed
+    Csv,
+    /// All values on one line, separated by commas
+    Joined,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [Format::Json, Format::Text, Format::Csv, Format::Joined];
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Text | Format::Joined => "text/plain; charset=utf-8",
+            Format::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Text => "text",
+            Format::Csv => "csv",
+            Format::Joined => "joined",
+        }
+    }
+
+    pub fn render(self, values: &[String]) -> Bytes {
+        match self {
+            Format::Json => render_json(values),
+            Format::Text => Bytes::from(values.iter().map(|value| format!("{}\n", value)).collect::<String>()),
+            Format::Csv => Bytes::from(values.iter().map(|value| format!("{}\r\n", csv_field(value))).collect::<String>()),
+            Format::Joined if values.is_empty() => Bytes::new(),
+            Format::Joined => Bytes::from(format!("{}\n", values.join(","))),
+        }
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Render values as the JSON array the endpoints respond with
+pub fn render_json(values: &[String]) -> Bytes {
+    // Serializing a list of strings cannot fail
+    Bytes::from(serde_json::to_vec(values).unwrap())
+}
+
+/// Hex encoded hash identifying a rendered body
+pub fn content_hash(body: &[u8]) -> String {
+    // Half of a SHA-256 is plenty to tell versions of one entry apart
+    hex::encode(&Sha256::digest(body)[..16])
+}
+
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub fn unix_time_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+pub fn cache_key(endpoint_path: &str, name: &str) -> String {
+    format!("{}:{}", endpoint_path, name)
+}
+
+/// Split a cache key back into its endpoint path and name
+pub fn split_cache_key(cache_key: &str) -> Option<(&str, &str)> {
+    let parts: Vec<&str> = cache_key.split(':').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+    Some((parts[0], parts[1]))
+}
+
+/// DNs are compared case-insensitively and without whitespace around separators
+pub fn normalize_dn(dn: &str) -> String {
+    dn.split(',')
+        .map(|rdn| rdn.trim().to_lowercase())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Check whether `dn` is `base` or lives somewhere below it. Both must already be normalized.
+pub fn dn_is_under(dn: &str, base: &str) -> bool {
+    dn == base || dn.ends_with(&format!(",{}", base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rendered_json() {
+        let entry = CacheEntry::new(vec!["alice".to_string(), "b\"ob".to_string()], vec![]);
+        assert_eq!(&entry.json()[..], br#"["alice","b\"ob"]"#);
+        // Clones share the rendered body
+        assert_eq!(entry.clone().json().as_ptr(), entry.json().as_ptr());
+
+        assert_eq!(&CacheEntry::new(vec![], vec![]).json()[..], b"[]");
+    }
+
+    #[test]
+    fn test_formats() {
+        let entry = CacheEntry::new(vec!["alice".to_string(), "Smith, Bob".to_string(), "say \"hi\"".to_string()], vec![]);
+        assert_eq!(&entry.body(Format::Text)[..], b"alice\nSmith, Bob\nsay \"hi\"\n");
+        assert_eq!(&entry.body(Format::Csv)[..], b"alice\r\n\"Smith, Bob\"\r\n\"say \"\"hi\"\"\"\r\n");
+        assert_eq!(&entry.body(Format::Joined)[..], b"alice,Smith, Bob,say \"hi\"\n");
+        // Bodies are rendered once
+        assert_eq!(entry.body(Format::Csv).as_ptr(), entry.body(Format::Csv).as_ptr());
+
+        let empty = CacheEntry::new(vec![], vec![]);
+        for format in [Format::Text, Format::Csv, Format::Joined] {
+            assert!(empty.body(format).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_diff_values() {
+        let old = vec!["alice".to_string(), "bob".to_string(), "carol".to_string()];
+        let new = vec!["dave".to_string(), "alice".to_string(), "carol".to_string()];
+        assert_eq!(diff_values(&old, &new), (vec!["dave".to_string()], vec!["bob".to_string()]));
+        assert_eq!(diff_values(&old, &old), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_insert_publishes_changes() {
+        let cache = Cache::new();
+        let mut changes = cache.subscribe();
+
+        // New keys are first fills, not changes of their values
+        let first = cache.insert(cache_key("/group_members", "staff"), CacheEntry::new(vec!["alice".to_string()], vec![]));
+        let change = changes.try_recv().unwrap();
+        assert!(change.first_fill);
+        assert!(change.added.is_empty() && change.removed.is_empty());
+        assert_eq!(change.entry.version, first.version);
+
+        // Unchanged values are not changes, and keep their version
+        let second = cache.insert(cache_key("/group_members", "staff"), CacheEntry::new(vec!["alice".to_string()], vec![]));
+        assert_eq!(first.version, second.version);
+        assert_eq!(first.modified_at, second.modified_at);
+        assert_eq!(first.content_hash(), second.content_hash());
+        assert!(changes.try_recv().is_err());
+
+        cache.insert(cache_key("/group_members", "staff"), CacheEntry::new(vec!["bob".to_string()], vec![]));
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.key, "/group_members:staff");
+        assert!(!change.first_fill);
+        assert!(change.entry.version > first.version);
+        assert_ne!(change.entry.content_hash(), first.content_hash());
+        assert_eq!(change.added, vec!["bob"]);
+        assert_eq!(change.removed, vec!["alice"]);
+        assert_eq!(change.entry.values, vec!["bob"]);
+
+        // An entry fetched again after it was removed is a first fill again
+        cache.remove("/group_members:staff");
+        let third = cache.insert(cache_key("/group_members", "staff"), CacheEntry::new(vec!["bob".to_string()], vec![]));
+        let change = changes.try_recv().unwrap();
+        assert!(change.first_fill);
+        assert_eq!(change.entry.version, third.version);
+    }
+}
@@ -1,8 +1,10 @@
 use getset::Getters;
 use serde::{Deserialize, Serialize};
-use std::{env, net::SocketAddr, fs, os::unix::fs::{PermissionsExt, MetadataExt}};
+use std::{collections::BTreeMap, env, net::SocketAddr, fs, os::unix::fs::{PermissionsExt, MetadataExt}};
 use log::error;
 
+use crate::{cache::Format, handler::RESERVED_PATHS};
+
 #[derive(Clone, Getters, Debug, Deserialize, Serialize)]
 pub struct Config {
     #[get = "pub"]
@@ -11,6 +13,11 @@ pub struct Config {
     server: ServerConfig,
     #[get = "pub"]
     endpoints: Vec<EndpointConfig>,
+    #[get = "pub"]
+    admin: Option<AdminConfig>,
+    #[get = "pub"]
+    #[serde(default)]
+    clients: Vec<ApiClientConfig>,
 }
 
 #[derive(Clone, Getters, Debug, Deserialize, Serialize)]
@@ -21,6 +28,8 @@ pub struct LdapConfig {
     bind_dn: String,
     #[get = "pub"]
     bind_password: String,
+    #[get = "pub"]
+    change_tracking: Option<ChangeTrackingConfig>,
 }
 
 impl LdapConfig {
@@ -30,103 +39,1047 @@ impl LdapConfig {
             return Err("LDAP URL cannot be empty".into());
         }
         
-        if !self.url.starts_with("ldap://") && !self.url.starts_with("ldaps://") {
-            return Err("LDAP URL must start with 'ldap://' or 'ldaps://'".into());
+        if !self.url.starts_with("ldap://") && !self.url.starts_with("ldaps://") {
+            return Err("LDAP URL must start with 'ldap://' or 'ldaps://'".into());
+        }
+        
+        // Validate bind DN
+        if self.bind_dn.is_empty() {
+            return Err("LDAP bind DN cannot be empty".into());
+        }
+        
+        // Validate bind password
+        if self.bind_password.is_empty() {
+            return Err("LDAP bind password cannot be empty".into());
+        }
+        
+        // Validate change tracking if present
+        if let Some(tracking) = &self.change_tracking {
+            tracking.validate()?;
+        }
+        
+        Ok(())
+    }
+}
+
+fn default_change_tracking_filter() -> String {
+    "(objectClass=*)".to_string()
+}
+
+fn default_retry_interval_secs() -> u64 {
+    60
+}
+
+fn default_poll_interval_secs() -> u64 {
+    30
+}
+
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct ChangeTrackingConfig {
+    #[get = "pub"]
+    r#type: String,
+    #[get = "pub"]
+    search_base: String,
+    #[get = "pub"]
+    #[serde(default = "default_change_tracking_filter")]
+    search_filter: String,
+    #[get = "pub"]
+    #[serde(default = "default_retry_interval_secs")]
+    retry_interval_secs: u64,
+    #[get = "pub"]
+    #[serde(default = "default_poll_interval_secs")]
+    poll_interval_secs: u64,
+    #[get = "pub"]
+    cookie_file: Option<String>,
+}
+
+impl ChangeTrackingConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate type
+        let valid_types = ["syncrepl", "dirsync", "usn_changed"];
+        if !valid_types.contains(&self.r#type.as_str()) {
+            return Err(format!("ldap.change_tracking.type must be one of: {}", valid_types.join(", ")).into());
+        }
+        
+        // Validate search base
+        if self.search_base.is_empty() {
+            return Err("ldap.change_tracking.search_base cannot be empty".into());
+        }
+        
+        // Validate search filter
+        if self.search_filter.is_empty() {
+            return Err("ldap.change_tracking.search_filter cannot be empty".into());
+        }
+        
+        // Validate retry interval
+        if self.retry_interval_secs == 0 {
+            return Err("ldap.change_tracking.retry_interval_secs must be greater than 0 seconds".into());
+        }
+        
+        // Validate poll interval
+        if self.poll_interval_secs == 0 {
+            return Err("ldap.change_tracking.poll_interval_secs must be greater than 0 seconds".into());
+        }
+        
+        // Validate cookie file
+        if self.cookie_file.as_ref().is_some_and(|path| path.is_empty()) {
+            return Err("ldap.change_tracking.cookie_file cannot be empty".into());
+        }
+        
+        Ok(())
+    }
+}
+
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct ServerConfig {
+    #[get = "pub"]
+    #[serde(default)]
+    bind_addr: Option<SocketAddr>,
+    #[get = "pub"]
+    #[serde(default)]
+    listen: Vec<ListenConfig>,
+    #[get = "pub"]
+    tls: Option<TlsConfig>,
+    #[get = "pub"]
+    refresh_interval_secs: u64,
+    #[get = "pub"]
+    snapshot: Option<SnapshotConfig>,
+    #[get = "pub"]
+    events: Option<EventsConfig>,
+    #[get = "pub"]
+    batch: Option<BatchConfig>,
+    #[get = "pub"]
+    limits: Option<LimitsConfig>,
+    #[get = "pub"]
+    path_prefix: Option<String>,
+    #[get = "pub"]
+    #[serde(default = "default_ready_max_refresh_intervals")]
+    ready_max_refresh_intervals: u32,
+}
+
+impl ServerConfig {
+    /// Path the routes of an endpoint are served under, behind `path_prefix` if set
+    pub fn route_path(&self, endpoint_path: &str) -> String {
+        format!("{}{}", self.path_prefix.as_deref().unwrap_or(""), endpoint_path)
+    }
+
+    /// Every address the server accepts connections on, `bind_addr` first
+    pub fn listeners(&self) -> Vec<ListenConfig> {
+        self.bind_addr.map(ListenConfig::tcp).into_iter()
+            .chain(self.listen.iter().cloned())
+            .collect()
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate listeners
+        for (i, listen) in self.listen.iter().enumerate() {
+            listen.validate(i)?;
+        }
+
+        let listeners = self.listeners();
+        if listeners.is_empty() {
+            return Err("server requires bind_addr or at least one listen address".into());
+        }
+
+        let mut addresses = std::collections::HashSet::new();
+        for listen in &listeners {
+            if !addresses.insert(listen.address()) {
+                return Err(format!("Duplicate server listen address: {}", listen.address()).into());
+            }
+        }
+
+        // Validate TLS if present, it applies to the TCP listeners
+        if let Some(tls) = &self.tls {
+            tls.validate()?;
+
+            if listeners.iter().all(|listen| listen.tcp_addr().is_none()) {
+                return Err("server.tls requires at least one TCP listen address".into());
+            }
+        }
+
+        // Validate refresh interval
+        if self.refresh_interval_secs == 0 {
+            return Err("Refresh interval must be greater than 0 seconds".into());
+        }
+        
+        if self.refresh_interval_secs > 86400 {
+            return Err("Refresh interval cannot exceed 24 hours (86400 seconds)".into());
+        }
+
+        if self.ready_max_refresh_intervals == 0 {
+            return Err("server.ready_max_refresh_intervals must be greater than 0".into());
+        }
+        
+        // Validate snapshot if present
+        if let Some(snapshot) = &self.snapshot {
+            snapshot.validate()?;
+        }
+        
+        // Validate events if present
+        if let Some(events) = &self.events {
+            events.validate()?;
+        }
+
+        // Validate batch lookups if present
+        if let Some(batch) = &self.batch {
+            batch.validate()?;
+        }
+
+        // Validate path prefix if present
+        if let Some(prefix) = &self.path_prefix {
+            if !prefix.starts_with('/') {
+                return Err("server.path_prefix must start with '/'".into());
+            }
+            if let Some(problem) = route_path_problem(prefix) {
+                return Err(format!("server.path_prefix {}", problem).into());
+            }
+            if prefix.contains('{') {
+                return Err("server.path_prefix cannot contain placeholders".into());
+            }
+        }
+
+        // Validate rate and concurrency limits if present
+        if let Some(limits) = &self.limits {
+            limits.validate()?;
+        }
+        
+        Ok(())
+    }
+}
+
+/// What keeps a path starting with '/' from being the base of routes, if anything
+fn route_path_problem(path: &str) -> Option<&'static str> {
+    if path.ends_with('/') {
+        return Some("must not end with '/'");
+    }
+    if path.contains("//") {
+        return Some("must not contain empty segments");
+    }
+    if path.contains([':', '*']) {
+        return Some("must not contain ':' or '*'");
+    }
+    // Braces may only enclose a whole segment naming a placeholder
+    if path.split('/').any(|segment| segment.contains(['{', '}']) && path_param(segment).is_none()) {
+        return Some("must only use '{' and '}' around a whole segment naming a placeholder");
+    }
+    None
+}
+
+/// An address the server accepts connections on, either `host:port` or `unix:` followed
+/// by the path of a unix domain socket
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct ListenConfig {
+    #[get = "pub"]
+    address: String,
+    #[get = "pub"]
+    mode: Option<String>,
+    #[get = "pub"]
+    owner: Option<String>,
+    #[get = "pub"]
+    group: Option<String>,
+}
+
+impl ListenConfig {
+    fn tcp(addr: SocketAddr) -> Self {
+        ListenConfig { address: addr.to_string(), mode: None, owner: None, group: None }
+    }
+
+    /// Path of the socket if this is a unix domain socket
+    pub fn unix_path(&self) -> Option<&str> {
+        self.address.strip_prefix("unix:")
+    }
+
+    /// Address to bind if this is a TCP listener
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        match self.unix_path() {
+            Some(_) => None,
+            None => self.address.parse().ok(),
+        }
+    }
+
+    /// Permissions of the socket file, owner only unless configured
+    pub fn mode_bits(&self) -> u32 {
+        self.mode.as_deref()
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .unwrap_or(0o600)
+    }
+
+    fn validate(&self, index: usize) -> Result<(), Box<dyn std::error::Error>> {
+        match self.unix_path() {
+            Some(path) => {
+                if !path.starts_with('/') {
+                    return Err(format!("server.listen[{}]: socket must be an absolute path", index).into());
+                }
+
+                if let Some(mode) = &self.mode
+                    && u32::from_str_radix(mode, 8).map_or(true, |mode| mode > 0o777)
+                {
+                    return Err(format!("server.listen[{}]: mode must be an octal permission such as \"0660\"", index).into());
+                }
+            }
+            None => {
+                if self.address.parse::<SocketAddr>().is_err() {
+                    return Err(format!("server.listen[{}]: address must be host:port or unix:/path", index).into());
+                }
+
+                if self.mode.is_some() || self.owner.is_some() || self.group.is_some() {
+                    return Err(format!("server.listen[{}]: mode, owner and group only apply to unix sockets", index).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn default_tls_reload_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct TlsConfig {
+    #[get = "pub"]
+    cert_file: String,
+    #[get = "pub"]
+    key_file: String,
+    #[get = "pub"]
+    client_ca_file: Option<String>,
+    /// `required` (the default) or `optional`, only with `client_ca_file`
+    #[get = "pub"]
+    client_auth: Option<String>,
+    #[get = "pub"]
+    #[serde(default = "default_tls_reload_interval_secs")]
+    reload_interval_secs: u64,
+}
+
+impl TlsConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate paths
+        for (name, path) in [("cert_file", Some(&self.cert_file)), ("key_file", Some(&self.key_file)), ("client_ca_file", self.client_ca_file.as_ref())] {
+            if let Some(path) = path
+                && !path.starts_with('/')
+            {
+                return Err(format!("server.tls.{} must be an absolute path", name).into());
+            }
+        }
+
+        // Validate client authentication
+        if let Some(client_auth) = &self.client_auth {
+            let valid_client_auth = ["required", "optional"];
+            if !valid_client_auth.contains(&client_auth.as_str()) {
+                return Err(format!("server.tls.client_auth must be one of: {}", valid_client_auth.join(", ")).into());
+            }
+            if self.client_ca_file.is_none() {
+                return Err("server.tls.client_auth requires server.tls.client_ca_file to verify client certificates against".into());
+            }
+        }
+
+        // Validate reload interval
+        if self.reload_interval_secs == 0 {
+            return Err("server.tls.reload_interval_secs must be greater than 0 seconds".into());
+        }
+
+        Ok(())
+    }
+}
+
+fn default_ready_max_refresh_intervals() -> u32 {
+    3
+}
+
+fn default_snapshot_interval_secs() -> u64 {
+    300
+}
+
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct SnapshotConfig {
+    #[get = "pub"]
+    path: String,
+    #[get = "pub"]
+    #[serde(default = "default_snapshot_interval_secs")]
+    interval_secs: u64,
+}
+
+impl SnapshotConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate path
+        if !self.path.starts_with('/') {
+            return Err("server.snapshot.path must be an absolute path".into());
+        }
+        
+        // Validate interval
+        if self.interval_secs == 0 {
+            return Err("server.snapshot.interval_secs must be greater than 0 seconds".into());
+        }
+        
+        Ok(())
+    }
+}
+
+fn default_batch_max_names() -> usize {
+    1000
+}
+
+fn default_batch_concurrency() -> usize {
+    8
+}
+
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct BatchConfig {
+    #[get = "pub"]
+    #[serde(default = "default_batch_max_names")]
+    max_names: usize,
+    #[get = "pub"]
+    #[serde(default = "default_batch_concurrency")]
+    concurrency: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_names: default_batch_max_names(),
+            concurrency: default_batch_concurrency(),
+        }
+    }
+}
+
+impl BatchConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.max_names == 0 {
+            return Err("server.batch.max_names must be greater than 0".into());
+        }
+
+        if self.concurrency == 0 || self.concurrency > 64 {
+            return Err("server.batch.concurrency must be between 1 and 64".into());
+        }
+
+        Ok(())
+    }
+}
+
+fn default_max_queued_misses() -> usize {
+    100
+}
+
+fn default_queue_timeout_secs() -> u64 {
+    5
+}
+
+/// Limits protecting the LDAP server from clients that request too much
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct LimitsConfig {
+    /// Sustained requests per second allowed to each client
+    #[get = "pub"]
+    requests_per_sec: Option<f64>,
+    /// Requests a client may make at once before being held to `requests_per_sec`
+    #[get = "pub"]
+    burst: Option<u32>,
+    /// Cache misses that may query LDAP at the same time across all clients
+    #[get = "pub"]
+    max_concurrent_misses: Option<usize>,
+    #[get = "pub"]
+    #[serde(default = "default_max_queued_misses")]
+    max_queued_misses: usize,
+    #[get = "pub"]
+    #[serde(default = "default_queue_timeout_secs")]
+    queue_timeout_secs: u64,
+}
+
+impl LimitsConfig {
+    /// Bucket size of the rate limit, one second worth of requests unless configured
+    pub fn burst_size(&self) -> Option<u32> {
+        let rate = self.requests_per_sec?;
+        Some(self.burst.unwrap_or_else(|| rate.ceil() as u32))
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(rate) = self.requests_per_sec
+            && !(rate.is_finite() && rate > 0.0)
+        {
+            return Err("server.limits.requests_per_sec must be greater than 0".into());
+        }
+
+        match self.burst {
+            Some(_) if self.requests_per_sec.is_none() => {
+                return Err("server.limits.burst requires requests_per_sec".into());
+            }
+            Some(0) => return Err("server.limits.burst must be greater than 0".into()),
+            _ => {}
+        }
+
+        if self.max_concurrent_misses == Some(0) {
+            return Err("server.limits.max_concurrent_misses must be greater than 0".into());
+        }
+
+        if self.queue_timeout_secs == 0 {
+            return Err("server.limits.queue_timeout_secs must be greater than 0".into());
+        }
+
+        Ok(())
+    }
+}
+
+fn default_events_buffer_size() -> usize {
+    1000
+}
+
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct EventsConfig {
+    #[get = "pub"]
+    log_file: Option<String>,
+    #[get = "pub"]
+    #[serde(default = "default_events_buffer_size")]
+    buffer_size: usize,
+    #[get = "pub"]
+    dead_letter_file: Option<String>,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            log_file: None,
+            buffer_size: default_events_buffer_size(),
+            dead_letter_file: None,
+        }
+    }
+}
+
+impl EventsConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate log file
+        if let Some(log_file) = &self.log_file
+            && !log_file.starts_with('/')
+        {
+            return Err("server.events.log_file must be an absolute path".into());
+        }
+        
+        // Validate dead letter file
+        if let Some(dead_letter_file) = &self.dead_letter_file
+            && !dead_letter_file.starts_with('/')
+        {
+            return Err("server.events.dead_letter_file must be an absolute path".into());
+        }
+        
+        // Validate buffer size
+        if self.buffer_size == 0 {
+            return Err("server.events.buffer_size must be greater than 0".into());
+        }
+        
+        Ok(())
+    }
+}
+
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct AdminConfig {
+    #[get = "pub"]
+    bind_addr: Option<SocketAddr>,
+    #[get = "pub"]
+    socket: Option<String>,
+    #[get = "pub"]
+    token: String,
+}
+
+impl AdminConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate listener, exactly one of bind_addr and socket
+        match (&self.bind_addr, &self.socket) {
+            (None, None) => return Err("admin requires either bind_addr or socket".into()),
+            (Some(_), Some(_)) => return Err("admin bind_addr and socket cannot both be set".into()),
+            (None, Some(socket)) if !socket.starts_with('/') => {
+                return Err("admin socket must be an absolute path".into());
+            }
+            _ => (),
+        }
+        
+        // Validate token
+        if self.token.len() < 16 {
+            return Err("admin token must be at least 16 characters".into());
+        }
+        
+        Ok(())
+    }
+}
+
+/// A client of the API, identified by a bearer token, a TLS client certificate subject
+/// or the uid of a process connecting over a unix socket
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct ApiClientConfig {
+    #[get = "pub"]
+    name: String,
+    #[get = "pub"]
+    token_sha256: Option<String>,
+    #[get = "pub"]
+    tls_subject: Option<String>,
+    #[get = "pub"]
+    unix_uid: Option<u32>,
+    /// Whether the client may bypass the cache for the live directory value
+    #[get = "pub"]
+    #[serde(default)]
+    live: bool,
+}
+
+impl ApiClientConfig {
+    fn validate(&self, index: usize) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate name
+        if self.name.is_empty() {
+            return Err(format!("Client {}: name cannot be empty", index).into());
+        }
+
+        // Validate credentials
+        if self.token_sha256.is_none() && self.tls_subject.is_none() && self.unix_uid.is_none() {
+            return Err(format!("Client {}: requires token_sha256, tls_subject or unix_uid", index).into());
+        }
+
+        if let Some(token_sha256) = &self.token_sha256
+            && (token_sha256.len() != 64 || !token_sha256.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Err(format!("Client {}: token_sha256 must be a hex encoded SHA-256 hash", index).into());
+        }
+
+        if self.tls_subject.as_ref().is_some_and(|subject| subject.is_empty()) {
+            return Err(format!("Client {}: tls_subject cannot be empty", index).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct EndpointConfig {
+    #[get = "pub"]
+    path: String,
+    #[get = "pub"]
+    search_base: String,
+    #[get = "pub"]
+    search_filter: String,
+    #[get = "pub"]
+    search_scope: String,
+    #[get = "pub"]
+    attribute: String,
+    #[get = "pub"]
+    result_processing: Option<ResultProcessing>,
+    #[get = "pub"]
+    preload: Option<PreloadConfig>,
+    #[get = "pub"]
+    mirror: Option<MirrorConfig>,
+    #[get = "pub"]
+    #[serde(default)]
+    webhooks: Vec<WebhookConfig>,
+    #[get = "pub"]
+    #[serde(default)]
+    envelope: bool,
+    #[get = "pub"]
+    #[serde(default)]
+    format: Format,
+    #[get = "pub"]
+    allow: Option<Vec<String>>,
+    #[get = "pub"]
+    #[serde(default)]
+    query: Vec<String>,
+    #[get = "pub"]
+    #[serde(default)]
+    wildcards: bool,
+}
+
+/// Query parameters the handlers use themselves, which placeholders cannot be bound to
+const RESERVED_QUERY_PARAMS: [&str; 3] = ["refresh", "envelope", "format"];
+
+/// Whether `name` can name a placeholder: letters, digits and `_`, not starting with a digit
+fn is_placeholder_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The named `{placeholder}` in a path segment, if the segment is one
+fn path_param(segment: &str) -> Option<&str> {
+    let name = segment.strip_prefix('{')?.strip_suffix('}')?;
+    is_placeholder_name(name).then_some(name)
+}
+
+/// Replace every named `{placeholder}` of `template` with the value `lookup` gives for it,
+/// in a single pass so values are never scanned for placeholders themselves. Braces that
+/// do not enclose a placeholder name are kept as they are.
+fn substitute<'a>(template: &'a str, mut lookup: impl FnMut(&'a str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}').map(|end| &after[..end]).filter(|name| is_placeholder_name(name)) {
+            Some(name) => {
+                result.push_str(&lookup(name).ok_or_else(|| format!("missing value for placeholder {}", name))?);
+                rest = &after[name.len() + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+impl EndpointConfig {
+    fn validate(&self, index: usize) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate path
+        if self.path.is_empty() {
+            return Err(format!("Endpoint {}: path cannot be empty", index).into());
+        }
+        
+        if !self.path.starts_with('/') {
+            return Err(format!("Endpoint {}: path must start with '/'", index).into());
+        }
+
+        if let Some(problem) = route_path_problem(&self.path) {
+            return Err(format!("Endpoint {}: path {}", index, problem).into());
+        }
+
+        // Validate search base
+        if self.search_base.is_empty() {
+            return Err(format!("Endpoint {}: search_base cannot be empty", index).into());
+        }
+        
+        // Validate search filter
+        if self.search_filter.is_empty() {
+            return Err(format!("Endpoint {}: search_filter cannot be empty", index).into());
+        }
+        
+        self.validate_placeholders(index)?;
+        
+        // Validate search scope
+        let valid_scopes = ["base", "one", "subtree"];
+        if !valid_scopes.contains(&self.search_scope.as_str()) {
+            return Err(format!("Endpoint {}: search_scope must be one of: {}", 
+                index, valid_scopes.join(", ")).into());
+        }
+        
+        // Validate attribute
+        if self.attribute.is_empty() {
+            return Err(format!("Endpoint {}: attribute cannot be empty", index).into());
+        }
+        
+        // Validate result processing if present
+        if let Some(processing) = &self.result_processing {
+            processing.validate(index)?;
+        }
+        
+        // Validate preload if present
+        if let Some(preload) = &self.preload {
+            preload.validate(index)?;
+            
+            if preload.enumerate && self.name_attribute().is_none() {
+                return Err(format!("Endpoint {}: preload.enumerate requires a search_filter of the form '(attribute={{}})'", index).into());
+            }
         }
         
-        // Validate bind DN
-        if self.bind_dn.is_empty() {
-            return Err("LDAP bind DN cannot be empty".into());
+        // Validate mirror if present
+        if let Some(mirror) = &self.mirror {
+            mirror.validate(index)?;
+            
+            if self.name_attribute().is_none() {
+                return Err(format!("Endpoint {}: mirror requires a search_filter of the form '(attribute={{}})'", index).into());
+            }
+            
+            if self.preload.is_some() {
+                return Err(format!("Endpoint {}: preload cannot be combined with mirror", index).into());
+            }
+            
+            if !self.webhooks.is_empty() {
+                return Err(format!("Endpoint {}: webhooks cannot be combined with mirror", index).into());
+            }
         }
         
-        // Validate bind password
-        if self.bind_password.is_empty() {
-            return Err("LDAP bind password cannot be empty".into());
+        // Validate webhooks
+        for webhook in &self.webhooks {
+            webhook.validate(index)?;
         }
         
         Ok(())
     }
+
+    /// How often the data of this endpoint is refreshed
+    pub fn refresh_interval_secs(&self, server: &ServerConfig) -> u64 {
+        self.mirror.as_ref()
+            .and_then(|mirror| mirror.refresh_interval_secs)
+            .unwrap_or(server.refresh_interval_secs)
+    }
+
+    /// Named placeholders of the search filter, e.g. `org` and `user` for
+    /// `(&(uid={user})(o={org}))`, empty when it uses the anonymous `{}`
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut names = vec![];
+        // Substituting nothing still walks every placeholder
+        let _ = substitute(&self.search_filter, |name| {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+            Some(String::new())
+        });
+        names
+    }
+
+    /// Placeholders bound from segments of the path, e.g. `org` and `user` for `/users/{org}/{user}`
+    pub fn path_params(&self) -> Vec<&str> {
+        self.path.split('/').filter_map(path_param).collect()
+    }
+
+    /// The path in axum's route syntax, `/users/:org/:user` for `/users/{org}/{user}`
+    pub fn route_pattern(&self) -> String {
+        self.path.split('/')
+            .map(|segment| match path_param(segment) {
+                Some(name) => format!(":{}", name),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Routes the endpoint is served on, in axum's syntax and behind `server.path_prefix`
+    pub fn routes(&self, server: &ServerConfig) -> EndpointRoutes {
+        let path = server.route_path(&self.route_pattern());
+        // Without named placeholders the name is the last path segment
+        let lookup = if self.placeholders().is_empty() { format!("{}/:name", path) } else { path.clone() };
+        EndpointRoutes {
+            batch: self.placeholders().is_empty().then(|| format!("{}/_batch", path)),
+            watch: self.mirror.is_none().then(|| format!("{}/watch", lookup)),
+            lookup,
+        }
+    }
+
+    /// The name a lookup is cached under, from the values bound to the named placeholders:
+    /// a query string ordered by placeholder, e.g. `org=acme&user=alice`
+    pub fn lookup_name(&self, values: &BTreeMap<&str, &str>) -> Result<String, String> {
+        let mut bound = BTreeMap::new();
+        for name in self.placeholders() {
+            match values.get(name) {
+                Some(value) if !value.is_empty() => bound.insert(name, *value),
+                _ => return Err(format!("missing parameter {}", name)),
+            };
+        }
+        serde_urlencoded::to_string(bound).map_err(|e| e.to_string())
+    }
+
+    /// Escape a value for the search filter, keeping `*` as a wildcard if the endpoint allows it
+    fn escape(&self, value: &str) -> String {
+        let escaped = ldap3::ldap_escape(value).into_owned();
+        if self.wildcards { escaped.replace("\\2a", "*") } else { escaped }
+    }
+
+    /// The search filter for a cached name. The anonymous `{}` placeholder is replaced by
+    /// the escaped name, named placeholders by the escaped values of a `lookup_name`.
+    pub fn filter(&self, name: &str) -> Result<String, String> {
+        if self.search_filter.contains("{}") {
+            return Ok(self.search_filter.replace("{}", &self.escape(name)));
+        }
+        let values: BTreeMap<String, String> = serde_urlencoded::from_str(name)
+            .map_err(|e| format!("invalid lookup name {}: {}", name, e))?;
+        substitute(&self.search_filter, |placeholder| values.get(placeholder).map(|value| self.escape(value)))
+    }
+
+    /// Check that every named placeholder is bound to a path segment or a query parameter,
+    /// and that everything bound is used
+    fn validate_placeholders(&self, index: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let placeholders = self.placeholders();
+        let path_params = self.path_params();
+
+        if placeholders.is_empty() {
+            if !self.search_filter.contains("{}") {
+                return Err(format!("Endpoint {}: search_filter must contain a '{{}}' or named placeholder", index).into());
+            }
+            if !path_params.is_empty() || !self.query.is_empty() {
+                return Err(format!("Endpoint {}: path parameters and query require named placeholders in search_filter", index).into());
+            }
+            return Ok(());
+        }
+
+        if self.search_filter.contains("{}") {
+            return Err(format!("Endpoint {}: search_filter cannot mix '{{}}' with named placeholders", index).into());
+        }
+
+        for name in &self.query {
+            if RESERVED_QUERY_PARAMS.contains(&name.as_str()) {
+                return Err(format!("Endpoint {}: query parameter {} is reserved", index, name).into());
+            }
+            if path_params.contains(&name.as_str()) {
+                return Err(format!("Endpoint {}: {} is bound by both the path and the query", index, name).into());
+            }
+        }
+
+        let mut bound = std::collections::HashSet::new();
+        for name in path_params.iter().copied().chain(self.query.iter().map(String::as_str)) {
+            if !bound.insert(name) {
+                return Err(format!("Endpoint {}: parameter {} is bound more than once", index, name).into());
+            }
+            if !placeholders.contains(&name) {
+                return Err(format!("Endpoint {}: parameter {} is not used in search_filter", index, name).into());
+            }
+        }
+
+        if let Some(unbound) = placeholders.iter().find(|name| !bound.contains(*name)) {
+            return Err(format!("Endpoint {}: placeholder {{{}}} is not bound to a path segment or query parameter", index, unbound).into());
+        }
+
+        if self.preload.is_some() {
+            return Err(format!("Endpoint {}: preload requires a '{{}}' placeholder", index).into());
+        }
+
+        Ok(())
+    }
+
+    /// The attribute compared against the `{}` placeholder in the search filter,
+    /// e.g. `cn` for `(&(objectClass=group)(cn={}))`
+    pub fn name_attribute(&self) -> Option<&str> {
+        let before = &self.search_filter[..self.search_filter.find("={})")?];
+        let attribute = &before[before.rfind('(')? + 1..];
+        if attribute.is_empty() || attribute.contains(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == ';')) {
+            return None;
+        }
+        Some(attribute)
+    }
+}
+
+/// The routes of one endpoint
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointRoutes {
+    pub lookup: String,
+    pub batch: Option<String>,
+    pub watch: Option<String>,
+}
+
+impl EndpointRoutes {
+    pub fn all(&self) -> Vec<&str> {
+        [Some(&self.lookup), self.batch.as_ref(), self.watch.as_ref()].into_iter().flatten().map(String::as_str).collect()
+    }
+}
+
+/// Whether two routes cannot be served side by side: axum refuses parameters of
+/// different names at the same position after a common prefix, and routes that can
+/// match the same request would hide one another
+fn routes_conflict(a: &str, b: &str) -> bool {
+    let a: Vec<&str> = a.split('/').collect();
+    let b: Vec<&str> = b.split('/').collect();
+    let is_param = |segment: &str| segment.starts_with(':');
+
+    for (x, y) in a.iter().zip(&b) {
+        if is_param(x) && is_param(y) {
+            if x != y {
+                return true;
+            }
+        } else if x != y {
+            break;
+        }
+    }
+
+    a.len() == b.len() && a.iter().zip(&b).all(|(x, y)| x == y || is_param(x) || is_param(y))
+}
+
+fn default_mirror_page_size() -> i32 {
+    500
 }
 
 #[derive(Clone, Getters, Debug, Deserialize, Serialize)]
-pub struct ServerConfig {
+pub struct MirrorConfig {
     #[get = "pub"]
-    bind_addr: SocketAddr,
+    #[serde(default = "default_mirror_page_size")]
+    page_size: i32,
     #[get = "pub"]
-    refresh_interval_secs: u64,
+    refresh_interval_secs: Option<u64>,
 }
 
-impl ServerConfig {
-    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
-        // Validate refresh interval
-        if self.refresh_interval_secs == 0 {
-            return Err("Refresh interval must be greater than 0 seconds".into());
+impl MirrorConfig {
+    fn validate(&self, endpoint_index: usize) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate page size
+        if self.page_size <= 0 {
+            return Err(format!("Endpoint {}: mirror.page_size must be greater than 0", endpoint_index).into());
         }
         
-        if self.refresh_interval_secs > 86400 {
-            return Err("Refresh interval cannot exceed 24 hours (86400 seconds)".into());
+        // Validate refresh interval
+        if self.refresh_interval_secs == Some(0) {
+            return Err(format!("Endpoint {}: mirror.refresh_interval_secs must be greater than 0 seconds", endpoint_index).into());
         }
         
         Ok(())
     }
 }
 
+fn default_webhook_timeout_secs() -> u64 {
+    10
+}
+
+fn default_webhook_max_attempts() -> u32 {
+    5
+}
+
+fn default_webhook_retry_backoff_secs() -> u64 {
+    2
+}
+
 #[derive(Clone, Getters, Debug, Deserialize, Serialize)]
-pub struct EndpointConfig {
-    #[get = "pub"]
-    path: String,
+pub struct WebhookConfig {
     #[get = "pub"]
-    search_base: String,
+    url: String,
     #[get = "pub"]
-    search_filter: String,
+    secret: String,
     #[get = "pub"]
-    search_scope: String,
+    #[serde(default = "default_webhook_timeout_secs")]
+    timeout_secs: u64,
     #[get = "pub"]
-    attribute: String,
+    #[serde(default = "default_webhook_max_attempts")]
+    max_attempts: u32,
     #[get = "pub"]
-    result_processing: Option<ResultProcessing>,
+    #[serde(default = "default_webhook_retry_backoff_secs")]
+    retry_backoff_secs: u64,
 }
 
-impl EndpointConfig {
-    fn validate(&self, index: usize) -> Result<(), Box<dyn std::error::Error>> {
-        // Validate path
-        if self.path.is_empty() {
-            return Err(format!("Endpoint {}: path cannot be empty", index).into());
-        }
-        
-        if !self.path.starts_with('/') {
-            return Err(format!("Endpoint {}: path must start with '/'", index).into());
-        }
-        
-        // Validate search base
-        if self.search_base.is_empty() {
-            return Err(format!("Endpoint {}: search_base cannot be empty", index).into());
+impl WebhookConfig {
+    fn validate(&self, endpoint_index: usize) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate URL
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(format!("Endpoint {}: webhook url must start with 'http://' or 'https://'", endpoint_index).into());
         }
         
-        // Validate search filter
-        if self.search_filter.is_empty() {
-            return Err(format!("Endpoint {}: search_filter cannot be empty", index).into());
+        // Validate secret
+        if self.secret.len() < 16 {
+            return Err(format!("Endpoint {}: webhook secret must be at least 16 characters", endpoint_index).into());
         }
         
-        if !self.search_filter.contains("{}") {
-            return Err(format!("Endpoint {}: search_filter must contain '{{}}' placeholder", index).into());
+        // Validate timeout
+        if self.timeout_secs == 0 {
+            return Err(format!("Endpoint {}: webhook timeout_secs must be greater than 0 seconds", endpoint_index).into());
         }
         
-        // Validate search scope
-        let valid_scopes = ["base", "one", "subtree"];
-        if !valid_scopes.contains(&self.search_scope.as_str()) {
-            return Err(format!("Endpoint {}: search_scope must be one of: {}", 
-                index, valid_scopes.join(", ")).into());
+        // Validate attempts
+        if self.max_attempts == 0 {
+            return Err(format!("Endpoint {}: webhook max_attempts must be greater than 0", endpoint_index).into());
         }
         
-        // Validate attribute
-        if self.attribute.is_empty() {
-            return Err(format!("Endpoint {}: attribute cannot be empty", index).into());
+        Ok(())
+    }
+}
+
+#[derive(Clone, Getters, Debug, Deserialize, Serialize)]
+pub struct PreloadConfig {
+    #[get = "pub"]
+    #[serde(default)]
+    names: Vec<String>,
+    #[get = "pub"]
+    file: Option<String>,
+    #[get = "pub"]
+    #[serde(default)]
+    enumerate: bool,
+}
+
+impl PreloadConfig {
+    fn validate(&self, endpoint_index: usize) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate that there is something to preload
+        if self.names.is_empty() && self.file.is_none() && !self.enumerate {
+            return Err(format!("Endpoint {}: preload requires names, file or enumerate", endpoint_index).into());
         }
         
-        // Validate result processing if present
-        if let Some(processing) = &self.result_processing {
-            processing.validate(index)?;
+        // Validate file
+        if self.file.as_ref().is_some_and(|file| !file.starts_with('/')) {
+            return Err(format!("Endpoint {}: preload.file must be an absolute path", endpoint_index).into());
         }
         
         Ok(())
@@ -159,40 +1112,46 @@ impl ResultProcessing {
     }
 }
 
+/// Check that a file holding sensitive data has secure permissions and ownership
+/// Only root should be able to read the file (600 or more restrictive)
+pub fn check_file_permissions(path: &str, description: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // Check if permission checks should be skipped
+    if env::var("DONTBLAMEME").unwrap_or_default() == "1" {
+        log::warn!("DONTBLAMEME=1 set, skipping {} permission checks", description.to_lowercase());
+        return Ok(());
+    }
+    
+    let metadata = fs::metadata(path)
+        .map_err(|e| format!("Failed to get {} metadata: {e}", description.to_lowercase()))?;
+    
+    let permissions = metadata.permissions();
+    let mode = permissions.mode();
+    
+    // Check if others have read access (mode & 0o004 != 0)
+    // Check if group has read access (mode & 0o040 != 0)
+    // Only owner should have read access (600 = 0o600)
+    if (mode & 0o077) != 0 {
+        error!("{} {} has insecure permissions: {:o}", description, path, mode);
+        error!("File permissions must be 600 or more restrictive (only owner can read)");
+        return Err(format!("{} has insecure permissions", description).into());
+    }
+    
+    // Check if file is owned by root (UID 0)
+    let uid = metadata.uid();
+    if uid != 0 {
+        error!("{} {} is not owned by root (UID: {})", description, path, uid);
+        error!("File must be owned by root for security");
+        return Err(format!("{} is not owned by root", description).into());
+    }
+    
+    Ok(())
+}
+
 impl Config {
     /// Check if the config file has secure permissions and ownership
     /// Only root should be able to read the file (600 or more restrictive)
     fn check_config_permissions(config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // Check if permission checks should be skipped
-        if env::var("DONTBLAMEME").unwrap_or_default() == "1" {
-            log::warn!("DONTBLAMEME=1 set, skipping config file permission checks");
-            return Ok(());
-        }
-        
-        let metadata = fs::metadata(config_path)
-            .map_err(|e| format!("Failed to get config file metadata: {e}"))?;
-        
-        let permissions = metadata.permissions();
-        let mode = permissions.mode();
-        
-        // Check if others have read access (mode & 0o004 != 0)
-        // Check if group has read access (mode & 0o040 != 0)
-        // Only owner should have read access (600 = 0o600)
-        if (mode & 0o077) != 0 {
-            error!("Config file {} has insecure permissions: {:o}", config_path, mode);
-            error!("File permissions must be 600 or more restrictive (only owner can read)");
-            return Err("Config file has insecure permissions".into());
-        }
-        
-        // Check if file is owned by root (UID 0)
-        let uid = metadata.uid();
-        if uid != 0 {
-            error!("Config file {} is not owned by root (UID: {})", config_path, uid);
-            error!("File must be owned by root for security");
-            return Err("Config file is not owned by root".into());
-        }
-        
-        Ok(())
+        check_file_permissions(config_path, "Config file")
     }
 
     /// Validate the entire configuration
@@ -203,6 +1162,20 @@ impl Config {
         // Validate server configuration
         self.server.validate()?;
         
+        // Validate admin configuration if present
+        if let Some(admin) = &self.admin {
+            admin.validate()?;
+            
+            let listeners = self.server.listeners();
+            if admin.bind_addr.is_some_and(|addr| listeners.iter().any(|listen| listen.tcp_addr() == Some(addr))) {
+                return Err("admin bind_addr must differ from the server listen addresses".into());
+            }
+
+            if admin.socket.as_deref().is_some_and(|socket| listeners.iter().any(|listen| listen.unix_path() == Some(socket))) {
+                return Err("admin socket must differ from the server listen sockets".into());
+            }
+        }
+        
         // Validate endpoints
         if self.endpoints.is_empty() {
             return Err("No endpoints configured. At least one endpoint is required.".into());
@@ -212,6 +1185,23 @@ impl Config {
             endpoint.validate(i)?;
         }
         
+        // Validate API clients
+        let mut client_names = std::collections::HashSet::new();
+        for (i, client) in self.clients.iter().enumerate() {
+            client.validate(i)?;
+            if !client_names.insert(client.name()) {
+                return Err(format!("Duplicate client name: {}", client.name()).into());
+            }
+        }
+
+        for (i, endpoint) in self.endpoints.iter().enumerate() {
+            for name in endpoint.allow().iter().flatten() {
+                if !client_names.contains(name) {
+                    return Err(format!("Endpoint {}: allow references unknown client {}", i, name).into());
+                }
+            }
+        }
+
         // Check for duplicate endpoint paths
         let mut paths = std::collections::HashSet::new();
         for endpoint in &self.endpoints {
@@ -219,7 +1209,22 @@ impl Config {
                 return Err(format!("Duplicate endpoint path: {}", endpoint.path()).into());
             }
         }
-        
+
+        // Check that the routes of every endpoint can be served next to the others
+        let routes: Vec<_> = self.endpoints.iter().map(|endpoint| endpoint.routes(&self.server)).collect();
+        for (i, endpoint_routes) in routes.iter().enumerate() {
+            for route in endpoint_routes.all() {
+                if let Some(reserved) = RESERVED_PATHS.iter().find(|reserved| routes_conflict(route, reserved)) {
+                    return Err(format!("Endpoint {}: route {} conflicts with {}", i, route, reserved).into());
+                }
+                for (j, other_routes) in routes.iter().enumerate().skip(i + 1) {
+                    if let Some(other) = other_routes.all().into_iter().find(|other| routes_conflict(route, other)) {
+                        return Err(format!("Endpoint {}: route {} conflicts with route {} of endpoint {}", i, route, other, j).into());
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 
@@ -316,10 +1321,19 @@ mod tests {
                 url: "ldaps://ldap.example.com:636".to_string(),
                 bind_dn: "cn=admin,dc=example,dc=com".to_string(),
                 bind_password: "secret".to_string(),
+                change_tracking: None,
             },
             server: ServerConfig {
-                bind_addr: "127.0.0.1:8080".parse().unwrap(),
+                bind_addr: Some("127.0.0.1:8080".parse().unwrap()),
+                listen: vec![],
+                tls: None,
                 refresh_interval_secs: 180,
+                snapshot: None,
+                events: None,
+                batch: None,
+                limits: None,
+                path_prefix: None,
+                ready_max_refresh_intervals: 3,
             },
             endpoints: vec![
                 EndpointConfig {
@@ -332,8 +1346,18 @@ mod tests {
                         r#type: "dn_translation".to_string(),
                         attribute: "uid".to_string(),
                     }),
+                    preload: None,
+                    mirror: None,
+                    webhooks: vec![],
+                    envelope: false,
+                    format: Format::Json,
+                    allow: None,
+                    query: vec![],
+                    wildcards: false,
                 }
             ],
+            admin: None,
+            clients: vec![],
         };
         
         assert!(config.validate().is_ok());
@@ -346,17 +1370,126 @@ mod tests {
                 url: "ldaps://ldap.example.com:636".to_string(),
                 bind_dn: "cn=admin,dc=example,dc=com".to_string(),
                 bind_password: "secret".to_string(),
+                change_tracking: None,
             },
             server: ServerConfig {
-                bind_addr: "127.0.0.1:8080".parse().unwrap(),
+                bind_addr: Some("127.0.0.1:8080".parse().unwrap()),
+                listen: vec![],
+                tls: None,
                 refresh_interval_secs: 180,
+                snapshot: None,
+                events: None,
+                batch: None,
+                limits: None,
+                path_prefix: None,
+                ready_max_refresh_intervals: 3,
             },
             endpoints: vec![],
+            admin: None,
+            clients: vec![],
         };
         
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_admin_validation() {
+        let mut admin = AdminConfig {
+            bind_addr: Some("127.0.0.1:8081".parse().unwrap()),
+            socket: None,
+            token: "0123456789abcdef".to_string(),
+        };
+        assert!(admin.validate().is_ok());
+        
+        admin.socket = Some("/run/ldap_cache_daemon/admin.sock".to_string());
+        assert!(admin.validate().is_err());
+        
+        admin.bind_addr = None;
+        assert!(admin.validate().is_ok());
+        
+        admin.token = "short".to_string();
+        assert!(admin.validate().is_err());
+    }
+
+    #[test]
+    fn test_server_listen_validation() {
+        let server = |listen: &str| serde_yaml::from_str::<ServerConfig>(&format!("refresh_interval_secs: 180\n{}", listen)).unwrap();
+
+        let config = server("listen:\n  - address: \"127.0.0.1:8080\"\n  - address: \"unix:/run/ldap_cache_daemon/api.sock\"\n    mode: \"0660\"\n    group: \"mail\"");
+        assert!(config.validate().is_ok());
+        let listeners = config.listeners();
+        assert_eq!(listeners[0].tcp_addr(), Some("127.0.0.1:8080".parse().unwrap()));
+        assert_eq!(listeners[1].unix_path(), Some("/run/ldap_cache_daemon/api.sock"));
+        assert_eq!(listeners[1].mode_bits(), 0o660);
+
+        // Nowhere to listen
+        assert!(server("").validate().is_err());
+        // bind_addr counts as a listener, but not twice
+        assert!(server("bind_addr: \"127.0.0.1:8080\"").validate().is_ok());
+        assert!(server("bind_addr: \"127.0.0.1:8080\"\nlisten:\n  - address: \"127.0.0.1:8080\"").validate().is_err());
+        assert!(server("listen:\n  - address: \"unix:run/api.sock\"").validate().is_err());
+        assert!(server("listen:\n  - address: \"unix:/run/api.sock\"\n    mode: \"rw\"").validate().is_err());
+        assert!(server("listen:\n  - address: \"127.0.0.1:8080\"\n    mode: \"0660\"").validate().is_err());
+
+        // TLS only applies to TCP listeners
+        let tls = "tls:\n  cert_file: \"/etc/pki/tls/certs/ldap_cache.pem\"\n  key_file: \"/etc/pki/tls/private/ldap_cache.key\"";
+        assert!(server(&format!("bind_addr: \"127.0.0.1:8443\"\n{}", tls)).validate().is_ok());
+        assert!(server(&format!("listen:\n  - address: \"unix:/run/api.sock\"\n{}", tls)).validate().is_err());
+        let mutual = format!("{}\n  client_ca_file: \"/etc/pki/tls/certs/internal_ca.pem\"", tls);
+        assert!(server(&format!("bind_addr: \"127.0.0.1:8443\"\n{}\n  client_auth: \"optional\"", mutual)).validate().is_ok());
+        assert!(server(&format!("bind_addr: \"127.0.0.1:8443\"\n{}\n  client_auth: \"sometimes\"", mutual)).validate().is_err());
+        // Client authentication without a CA to verify against
+        assert!(server(&format!("bind_addr: \"127.0.0.1:8443\"\n{}\n  client_auth: \"optional\"", tls)).validate().is_err());
+
+        // Path prefix
+        let prefixed = server("bind_addr: \"127.0.0.1:8080\"\npath_prefix: \"/ldap/v1\"");
+        assert!(prefixed.validate().is_ok());
+        assert_eq!(prefixed.route_path("/groups"), "/ldap/v1/groups");
+        assert!(server("bind_addr: \"127.0.0.1:8080\"\npath_prefix: \"ldap\"").validate().is_err());
+        assert!(server("bind_addr: \"127.0.0.1:8080\"\npath_prefix: \"/ldap/\"").validate().is_err());
+
+        // Limits
+        let limits = server("bind_addr: \"127.0.0.1:8080\"\nlimits:\n  requests_per_sec: 2.5\n  max_concurrent_misses: 4");
+        assert!(limits.validate().is_ok());
+        assert_eq!(limits.limits().as_ref().unwrap().burst_size(), Some(3));
+        assert!(server("bind_addr: \"127.0.0.1:8080\"\nlimits:\n  burst: 10").validate().is_err());
+        assert!(server("bind_addr: \"127.0.0.1:8080\"\nlimits:\n  requests_per_sec: 0").validate().is_err());
+        assert!(server("bind_addr: \"127.0.0.1:8080\"\nlimits:\n  max_concurrent_misses: 0").validate().is_err());
+    }
+
+    #[test]
+    fn test_api_clients_validation() {
+        let config = |clients: &str, allow: &str| serde_yaml::from_str::<Config>(&format!(r#"
+ldap:
+  url: "ldap://ldap.example.com:389"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+clients:
+{}
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={{}})"
+    search_scope: "subtree"
+    attribute: "member"
+    allow: {}
+"#, clients, allow)).unwrap();
+        let mail = "  - name: \"mail\"\n    tls_subject: \"CN=mail.example.com\"";
+        let token = "a".repeat(64);
+
+        assert!(config(mail, "[\"mail\"]").validate().is_ok());
+        assert!(config(&format!("{}\n  - name: \"portal\"\n    token_sha256: \"{}\"", mail, token), "[\"portal\"]").validate().is_ok());
+        // Unknown client in the allow list
+        assert!(config(mail, "[\"portal\"]").validate().is_err());
+        // Duplicate names, missing credentials and tokens that are not hashed
+        assert!(config(&format!("{}\n{}", mail, mail), "[\"mail\"]").validate().is_err());
+        assert!(config("  - name: \"mail\"", "[\"mail\"]").validate().is_err());
+        assert!(config("  - name: \"mail\"\n    token_sha256: \"s3cret\"", "[\"mail\"]").validate().is_err());
+    }
+
     #[test]
     fn test_endpoint_validation_invalid_path() {
         let endpoint = EndpointConfig {
@@ -366,11 +1499,231 @@ mod tests {
             search_scope: "subtree".to_string(),
             attribute: "member".to_string(),
             result_processing: None,
+            preload: None,
+            mirror: None,
+            webhooks: vec![],
+            envelope: false,
+            format: Format::Json,
+            allow: None,
+            query: vec![],
+            wildcards: false,
+        };
+        
+        assert!(endpoint.validate(0).is_err());
+
+        // Nested paths are fine as long as axum can route them
+        let endpoint = EndpointConfig { path: "/v1/groups".to_string(), ..endpoint };
+        assert!(endpoint.validate(0).is_ok());
+        for path in ["/v1/groups/", "/v1//groups", "/v1/:groups"] {
+            let endpoint = EndpointConfig { path: path.to_string(), ..endpoint.clone() };
+            assert!(endpoint.validate(0).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_change_tracking_validation() {
+        let mut tracking = ChangeTrackingConfig {
+            r#type: "syncrepl".to_string(),
+            search_base: "dc=example,dc=com".to_string(),
+            search_filter: default_change_tracking_filter(),
+            retry_interval_secs: default_retry_interval_secs(),
+            poll_interval_secs: default_poll_interval_secs(),
+            cookie_file: None,
+        };
+        assert!(tracking.validate().is_ok());
+        
+        tracking.r#type = "persistent_search".to_string();
+        assert!(tracking.validate().is_err());
+        
+        tracking.r#type = "dirsync".to_string();
+        tracking.cookie_file = Some("/var/lib/ldap_cache_daemon/dirsync.cookie".to_string());
+        assert!(tracking.validate().is_ok());
+        
+        tracking.poll_interval_secs = 0;
+        assert!(tracking.validate().is_err());
+        tracking.poll_interval_secs = default_poll_interval_secs();
+        
+        tracking.r#type = "syncrepl".to_string();
+        tracking.search_base = String::new();
+        assert!(tracking.validate().is_err());
+    }
+
+    #[test]
+    fn test_endpoint_name_attribute() {
+        let mut endpoint = EndpointConfig {
+            path: "/groups".to_string(),
+            search_base: "ou=groups,dc=example,dc=com".to_string(),
+            search_filter: "(&(objectClass=groupOfNames)(cn={}))".to_string(),
+            search_scope: "subtree".to_string(),
+            attribute: "member".to_string(),
+            result_processing: None,
+            preload: Some(PreloadConfig { names: vec![], file: None, enumerate: true }),
+            mirror: None,
+            webhooks: vec![],
+            envelope: false,
+            format: Format::Json,
+            allow: None,
+            query: vec![],
+            wildcards: false,
+        };
+        assert_eq!(endpoint.name_attribute(), Some("cn"));
+        assert!(endpoint.validate(0).is_ok());
+        
+        // The placeholder is only part of the value, so enumerating would not yield names
+        endpoint.search_filter = "(mail={}@example.com)".to_string();
+        assert_eq!(endpoint.name_attribute(), None);
+        assert!(endpoint.validate(0).is_err());
+        
+        endpoint.preload = Some(PreloadConfig { names: vec![], file: None, enumerate: false });
+        assert!(endpoint.validate(0).is_err());
+    }
+
+    #[test]
+    fn test_endpoint_validation_mirror() {
+        let mut endpoint = EndpointConfig {
+            path: "/groups".to_string(),
+            search_base: "ou=groups,dc=example,dc=com".to_string(),
+            search_filter: "(cn={})".to_string(),
+            search_scope: "subtree".to_string(),
+            attribute: "member".to_string(),
+            result_processing: None,
+            preload: None,
+            mirror: Some(MirrorConfig { page_size: default_mirror_page_size(), refresh_interval_secs: None }),
+            webhooks: vec![],
+            envelope: false,
+            format: Format::Json,
+            allow: None,
+            query: vec![],
+            wildcards: false,
         };
+        assert!(endpoint.validate(0).is_ok());
+        
+        endpoint.preload = Some(PreloadConfig { names: vec!["staff".to_string()], file: None, enumerate: false });
+        assert!(endpoint.validate(0).is_err());
+        
+        endpoint.preload = None;
+        endpoint.mirror = Some(MirrorConfig { page_size: 0, refresh_interval_secs: None });
+        assert!(endpoint.validate(0).is_err());
+    }
+
+    #[test]
+    fn test_endpoint_validation_webhooks() {
+        let mut endpoint: EndpointConfig = serde_yaml::from_str(r#"
+path: "/groups"
+search_base: "ou=groups,dc=example,dc=com"
+search_filter: "(cn={})"
+search_scope: "subtree"
+attribute: "member"
+webhooks:
+  - url: "https://mail-router.example.com/hooks/groups"
+    secret: "a_long_webhook_secret"
+"#).unwrap();
+        assert!(endpoint.validate(0).is_ok());
+        assert_eq!(*endpoint.webhooks()[0].max_attempts(), 5);
+        
+        endpoint.webhooks[0].secret = "short".to_string();
+        assert!(endpoint.validate(0).is_err());
+        
+        endpoint.webhooks[0].secret = "a_long_webhook_secret".to_string();
+        endpoint.webhooks[0].url = "ftp://mail-router.example.com".to_string();
+        assert!(endpoint.validate(0).is_err());
         
+        endpoint.webhooks[0].url = "https://mail-router.example.com/hooks/groups".to_string();
+        endpoint.mirror = Some(MirrorConfig { page_size: default_mirror_page_size(), refresh_interval_secs: None });
         assert!(endpoint.validate(0).is_err());
     }
 
+    #[test]
+    fn test_route_conflicts() {
+        let config = |endpoints: &[(&str, &str, &str)]| {
+            let endpoints: String = endpoints.iter().map(|(path, filter, query)| format!(r#"
+  - path: "{}"
+    search_base: "ou=people,dc=example,dc=com"
+    search_filter: "{}"
+    search_scope: "subtree"
+    attribute: "mail"
+    query: [{}]"#, path, filter, query)).collect();
+            serde_yaml::from_str::<Config>(&format!(r#"
+ldap:
+  url: "ldap://ldap.example.com:389"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+endpoints:{}
+"#, endpoints)).unwrap()
+        };
+
+        let users = ("/users", "(uid={})", "");
+        let groups = ("/groups", "(cn={})", "");
+        assert!(config(&[users, groups]).validate().is_ok());
+        assert!(config(&[users, ("/users/{name}/people", "(o={name})", "")]).validate().is_ok());
+
+        // /users/:name and /users/:org cannot be routed side by side
+        let err = config(&[users, ("/users/{org}", "(o={org})", "")]).validate().unwrap_err();
+        assert_eq!(err.to_string(), "Endpoint 0: route /users/:name conflicts with route /users/:org of endpoint 1");
+        // /v1/groups/watch would be both a lookup of "watch" and a watch of "groups"
+        assert!(config(&[("/v1", "(cn={})", ""), ("/v1/groups", "(cn={})", "")]).validate().is_err());
+        assert!(config(&[users, ("/users/{org}/people", "(o={org})", "")]).validate().is_err());
+        // Same parameter names: /users/_batch would be both a batch and a lookup of "_batch"
+        assert!(config(&[users, ("/users/{name}", "(&(o={name})(uid={user}))", "\"user\"")]).validate().is_err());
+        // A placeholder at the root hides the daemon's own routes
+        assert!(config(&[("/{org}", "(o={org})", "")]).validate().is_err());
+        // but an endpoint named like one of them only has routes below it
+        assert!(config(&[("/status", "(cn={})", "")]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_named_placeholders() {
+        let endpoint = |path: &str, filter: &str, query: &[&str]| EndpointConfig {
+            path: path.to_string(),
+            search_base: "ou=people,dc=example,dc=com".to_string(),
+            search_filter: filter.to_string(),
+            search_scope: "subtree".to_string(),
+            attribute: "mail".to_string(),
+            result_processing: None,
+            preload: None,
+            mirror: None,
+            webhooks: vec![],
+            envelope: false,
+            format: Format::Json,
+            allow: None,
+            query: query.iter().map(|name| name.to_string()).collect(),
+            wildcards: false,
+        };
+
+        let users = endpoint("/users/{org}", "(&(uid={user})(o={org})(objectClass=person))", &["user"]);
+        assert!(users.validate(0).is_ok());
+        assert_eq!(users.placeholders(), vec!["user", "org"]);
+        assert_eq!(users.route_pattern(), "/users/:org");
+
+        // Values are escaped and the lookup name does not depend on the order they came in
+        let name = users.lookup_name(&BTreeMap::from([("user", "a*)(uid=b"), ("org", "acme & co")])).unwrap();
+        assert_eq!(name, "org=acme+%26+co&user=a*%29%28uid%3Db");
+        assert_eq!(users.filter(&name).unwrap(), "(&(uid=a\\2a\\29\\28uid=b)(o=acme & co)(objectClass=person))");
+        assert!(users.lookup_name(&BTreeMap::from([("org", "acme")])).is_err());
+        assert!(users.lookup_name(&BTreeMap::from([("org", "acme"), ("user", "")])).is_err());
+
+        // The anonymous placeholder keeps working as before
+        let groups = endpoint("/groups", "(cn={})", &[]);
+        assert!(groups.validate(0).is_ok());
+        assert_eq!(groups.filter("staff").unwrap(), "(cn=staff)");
+        assert_eq!(groups.filter("x)(cn=*").unwrap(), "(cn=x\\29\\28cn=\\2a)");
+        // Wildcards are only kept when the endpoint allows them
+        let groups = EndpointConfig { wildcards: true, ..groups };
+        assert_eq!(groups.filter("st*)").unwrap(), "(cn=st*\\29)");
+
+        // Every placeholder must be bound, once, and everything bound must be used
+        assert!(endpoint("/users/{org}", "(&(uid={user})(o={org}))", &[]).validate(0).is_err());
+        assert!(endpoint("/users/{org}", "(uid={user})", &["user"]).validate(0).is_err());
+        assert!(endpoint("/users/{org}", "(&(uid={user})(o={org}))", &["user", "org"]).validate(0).is_err());
+        assert!(endpoint("/users", "(&(uid={user})(cn={}))", &["user"]).validate(0).is_err());
+        assert!(endpoint("/users", "(uid={format})", &["format"]).validate(0).is_err());
+        assert!(endpoint("/users/{org}", "(cn={})", &[]).validate(0).is_err());
+        assert!(endpoint("/users/x{org}", "(o={org})", &[]).validate(0).is_err());
+    }
+
     #[test]
     fn test_endpoint_validation_missing_placeholder() {
         let endpoint = EndpointConfig {
@@ -380,6 +1733,14 @@ mod tests {
             search_scope: "subtree".to_string(),
             attribute: "member".to_string(),
             result_processing: None,
+            preload: None,
+            mirror: None,
+            webhooks: vec![],
+            envelope: false,
+            format: Format::Json,
+            allow: None,
+            query: vec![],
+            wildcards: false,
         };
         
         assert!(endpoint.validate(0).is_err());
@@ -0,0 +1,342 @@
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    sync::{Arc, atomic::Ordering},
+};
+
+use bytes::BytesMut;
+use ldap3::{
+    Ldap, Scope, SearchEntry,
+    asn1::{ASNTag, Integer, OctetString, Sequence, Tag, parse_tag, parse_uint, write},
+    controls::{MakeCritical, RawControl},
+};
+use log::{debug, error, info, warn};
+use tokio::time::{Duration, sleep};
+
+use crate::{
+    AppState,
+    config::ChangeTrackingConfig,
+    ldap::connect_and_bind,
+    sync::{SyncActive, apply_change},
+};
+
+const DIRSYNC_OID: &str = "1.2.840.113556.1.4.841";
+
+/// Only return objects and attributes the bind DN is allowed to read, so the
+/// "Replicating Directory Changes" right is not required
+const DIRSYNC_OBJECT_SECURITY: i64 = 0x0000_0001;
+
+/// Active Directory DirSync request control
+struct DirSync {
+    flags: i64,
+    max_bytes: i64,
+    cookie: Vec<u8>,
+}
+
+impl MakeCritical for DirSync {}
+
+impl From<DirSync> for RawControl {
+    fn from(ds: DirSync) -> RawControl {
+        let cookie_len = ds.cookie.len();
+        let val = Tag::Sequence(Sequence {
+            inner: vec![
+                Tag::Integer(Integer {
+                    inner: ds.flags,
+                    ..Default::default()
+                }),
+                Tag::Integer(Integer {
+                    inner: ds.max_bytes,
+                    ..Default::default()
+                }),
+                Tag::OctetString(OctetString {
+                    inner: ds.cookie,
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        })
+        .into_structure();
+        let mut buf = BytesMut::with_capacity(cookie_len + 16);
+        write::encode_into(&mut buf, val).expect("encoded");
+        RawControl {
+            ctype: DIRSYNC_OID.to_owned(),
+            crit: false,
+            val: Some(Vec::from(&buf[..])),
+        }
+    }
+}
+
+/// Parse the DirSync response control value into its "more results" flag and new cookie
+fn parse_dirsync_response(val: &[u8]) -> Option<(bool, Vec<u8>)> {
+    let (_, tag) = parse_tag(val).ok()?;
+    let mut comps = tag.expect_constructed()?.into_iter();
+    let more_results = comps.next()?.expect_primitive()?;
+    let (_, more_results) = parse_uint(&more_results).ok()?;
+    let _unused = comps.next()?;
+    let cookie = comps.next()?.expect_primitive()?;
+    Some((more_results != 0, cookie))
+}
+
+/// Parse a `uSNChanged` cookie, the last seen USN as text
+fn parse_usn(cookie: &[u8]) -> Option<u64> {
+    std::str::from_utf8(cookie).ok()?.trim().parse().ok()
+}
+
+/// Read the cookie saved by the last run, `None` to start over with a full synchronization
+fn load_cookie(tracking: &ChangeTrackingConfig) -> Option<Vec<u8>> {
+    let path = tracking.cookie_file().as_ref()?;
+    match fs::read(path) {
+        // DirSync cookies are opaque, only USNs can be checked
+        Ok(cookie) if tracking.r#type() != "dirsync" && parse_usn(&cookie).is_none() => {
+            warn!("Ignoring invalid change tracking cookie in {}, starting with a full synchronization", path);
+            None
+        }
+        Ok(cookie) if !cookie.is_empty() => {
+            info!("Loaded change tracking cookie from {}", path);
+            Some(cookie)
+        }
+        Ok(_) => None,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            warn!("Failed to read change tracking cookie from {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Write the cookie next to its final location first so a crash never leaves a truncated cookie behind
+fn save_cookie(tracking: &ChangeTrackingConfig, cookie: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(path) = tracking.cookie_file() else {
+        return Ok(());
+    };
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)?;
+    file.write_all(cookie)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Poll Active Directory for changed objects with either the DirSync control or
+/// `uSNChanged` searches, refreshing the cached entries that reference them.
+///
+/// The cookie (the DirSync cookie, or the last seen USN) is persisted to `cookie_file`
+/// after every successful poll. While polls succeed `sync_active` is set so the regular
+/// polling refresh is skipped; on failure it is cleared and the poll retried after
+/// `retry_interval_secs`.
+pub async fn run_dirsync(state: Arc<AppState>, sync_active: SyncActive) {
+    let Some(tracking) = state.config.ldap().change_tracking().clone() else {
+        return;
+    };
+    let poll_interval = Duration::from_secs(*tracking.poll_interval_secs());
+    let retry_interval = Duration::from_secs(*tracking.retry_interval_secs());
+
+    let mut cookie = load_cookie(&tracking);
+
+    loop {
+        let initial = cookie.is_none();
+        let result = match tracking.r#type().as_str() {
+            "dirsync" => poll_dirsync(&state, &tracking, &mut cookie).await,
+            _ => poll_usn_changed(&state, &tracking, &mut cookie).await,
+        }
+        .map_err(|e| e.to_string());
+
+        match result {
+            Ok(changed) => {
+                debug!("{} poll found {} changed objects", tracking.r#type(), changed);
+                if let Some(cookie) = &cookie
+                    && let Err(e) = save_cookie(&tracking, cookie)
+                {
+                    error!("Failed to save change tracking cookie: {}", e);
+                }
+                if !sync_active.swap(true, Ordering::SeqCst) {
+                    info!("{} change tracking established, polling suspended", tracking.r#type());
+                    if initial {
+                        // Pick up anything that changed since the last poll
+                        crate::refresh_cache(state.clone()).await;
+                    }
+                }
+                sleep(poll_interval).await;
+            }
+            Err(e) => {
+                error!("{} poll failed, falling back to polling: {}", tracking.r#type(), e);
+                sync_active.store(false, Ordering::SeqCst);
+                sleep(retry_interval).await;
+            }
+        }
+    }
+}
+
+/// Run one DirSync round, following "more results" until the server has nothing left.
+/// Without a cookie this is the initial synchronization, whose objects are skipped.
+async fn poll_dirsync(
+    state: &AppState,
+    tracking: &ChangeTrackingConfig,
+    cookie: &mut Option<Vec<u8>>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut ldap = connect_and_bind(state.config.ldap(), &state.metrics).await?;
+    let initial = cookie.is_none();
+    let mut changed = 0;
+
+    loop {
+        let control = DirSync {
+            flags: DIRSYNC_OBJECT_SECURITY,
+            max_bytes: 0,
+            cookie: cookie.clone().unwrap_or_default(),
+        };
+        let (entries, result) = ldap
+            .with_controls(control.critical())
+            .search(tracking.search_base(), Scope::Subtree, tracking.search_filter(), vec!["objectGUID"])
+            .await?
+            .success()?;
+
+        let response = result.ctrls.iter()
+            .find(|ctrl| ctrl.1.ctype == DIRSYNC_OID)
+            .and_then(|ctrl| ctrl.1.val.as_deref())
+            .and_then(parse_dirsync_response)
+            .ok_or("DirSync response control missing or malformed")?;
+        let (more_results, new_cookie) = response;
+
+        if !initial {
+            for entry in entries {
+                let entry = SearchEntry::construct(entry);
+                apply_change(&mut ldap, state, &entry.dn).await;
+                changed += 1;
+            }
+        }
+
+        *cookie = Some(new_cookie);
+        if !more_results {
+            break;
+        }
+    }
+
+    Ok(changed)
+}
+
+async fn highest_committed_usn(ldap: &mut Ldap) -> Result<u64, Box<dyn std::error::Error>> {
+    let (entries, _) = ldap
+        .search("", Scope::Base, "(objectClass=*)", vec!["highestCommittedUSN"])
+        .await?
+        .success()?;
+    let entry = SearchEntry::construct(entries.into_iter().next().ok_or("rootDSE not readable")?);
+    let usn = entry.attrs.get("highestCommittedUSN")
+        .and_then(|vals| vals.first())
+        .ok_or("rootDSE has no highestCommittedUSN")?;
+    Ok(usn.parse()?)
+}
+
+/// Search for objects whose `uSNChanged` moved past the last seen USN. The cookie holds
+/// that USN as text; without one we only record the current USN.
+///
+/// USNs are per domain controller, so the LDAP URL should always reach the same one.
+async fn poll_usn_changed(
+    state: &AppState,
+    tracking: &ChangeTrackingConfig,
+    cookie: &mut Option<Vec<u8>>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut ldap = connect_and_bind(state.config.ldap(), &state.metrics).await?;
+    let highest = highest_committed_usn(&mut ldap).await?;
+    let mut changed = 0;
+
+    if let Some(last) = cookie.as_deref() {
+        let last = parse_usn(last).ok_or("invalid uSNChanged cookie")?;
+        if highest > last {
+            let filter = format!("(&{}(uSNChanged>={})(!(uSNChanged>={})))",
+                tracking.search_filter(), last + 1, highest + 1);
+            let (entries, _) = ldap
+                .search(tracking.search_base(), Scope::Subtree, &filter, vec!["uSNChanged"])
+                .await?
+                .success()?;
+
+            for entry in entries {
+                let entry = SearchEntry::construct(entry);
+                apply_change(&mut ldap, state, &entry.dn).await;
+                changed += 1;
+            }
+        }
+    }
+
+    *cookie = Some(highest.to_string().into_bytes());
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dirsync_response_parsing() {
+        // The response control has the same layout as the request
+        let raw = RawControl::from(DirSync {
+            flags: 1,
+            max_bytes: 0,
+            cookie: b"cookie".to_vec(),
+        });
+        let (more_results, cookie) = parse_dirsync_response(raw.val.as_ref().unwrap()).unwrap();
+        assert!(more_results);
+        assert_eq!(cookie, b"cookie");
+
+        assert!(parse_dirsync_response(b"garbage").is_none());
+    }
+
+    fn tracking(r#type: &str, cookie_file: Option<&std::path::Path>) -> ChangeTrackingConfig {
+        let cookie_file = cookie_file.map(|path| format!("cookie_file: \"{}\"", path.display())).unwrap_or_default();
+        serde_yaml::from_str(&format!("type: \"{}\"\nsearch_base: \"dc=example,dc=com\"\n{}", r#type, cookie_file)).unwrap()
+    }
+
+    #[test]
+    fn test_cookie_round_trip() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("cookie");
+
+        // DirSync cookies are binary
+        let dirsync = tracking("dirsync", Some(&path));
+        let cookie = vec![0x4d, 0x53, 0x44, 0x53, 0x00, 0xff, 0x03];
+        save_cookie(&dirsync, &cookie).unwrap();
+        assert_eq!(load_cookie(&dirsync), Some(cookie));
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(!dir.path().join("cookie.tmp").exists());
+
+        // uSNChanged cookies hold the last seen USN, a new one replaces the old
+        let usn_changed = tracking("usn_changed", Some(&path));
+        save_cookie(&usn_changed, b"12345").unwrap();
+        save_cookie(&usn_changed, b"12400").unwrap();
+        let cookie = load_cookie(&usn_changed).unwrap();
+        assert_eq!(parse_usn(&cookie), Some(12400));
+
+        // Without a cookie file nothing is kept
+        let unsaved = tracking("usn_changed", None);
+        save_cookie(&unsaved, b"12345").unwrap();
+        assert_eq!(load_cookie(&unsaved), None);
+    }
+
+    #[test]
+    fn test_cookie_fallback() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("cookie");
+
+        // No cookie yet: start with a full synchronization
+        assert_eq!(load_cookie(&tracking("dirsync", Some(&path))), None);
+        assert_eq!(load_cookie(&tracking("usn_changed", Some(&path))), None);
+
+        // An empty or corrupt cookie is not used either
+        fs::write(&path, b"").unwrap();
+        assert_eq!(load_cookie(&tracking("dirsync", Some(&path))), None);
+        assert_eq!(load_cookie(&tracking("usn_changed", Some(&path))), None);
+        fs::write(&path, b"\x00garbage").unwrap();
+        assert_eq!(load_cookie(&tracking("usn_changed", Some(&path))), None);
+
+        // A cookie file that cannot be read
+        let unreadable = tracking("dirsync", Some(dir.path()));
+        assert_eq!(load_cookie(&unreadable), None);
+    }
+}
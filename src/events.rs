@@ -0,0 +1,214 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::fs::OpenOptionsExt,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, Receiver};
+
+use crate::cache::{CacheChange, split_cache_key, unix_time, unix_time_ms};
+
+/// A change to the values of one cached entry, as written to the event log
+#[derive(Clone, Debug, Serialize)]
+pub struct ChangeEvent {
+    pub id: u64,
+    pub at: u64,
+    pub endpoint: String,
+    pub name: String,
+    /// Version of the entry after the change, orders the changes of one name
+    pub version: u64,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Number of events kept for subscribers that fall behind
+const EVENT_CAPACITY: usize = 4096;
+
+/// How much of the event log file is read at a time when looking for its last event
+const TAIL_CHUNK: u64 = 4096;
+
+/// The most recent change events, oldest first, and the event log file they are appended to
+pub struct EventLog {
+    capacity: usize,
+    recent: Mutex<VecDeque<ChangeEvent>>,
+    next_id: AtomicU64,
+    events: broadcast::Sender<ChangeEvent>,
+    log_file: Option<Mutex<File>>,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize, mut log_file: Option<File>) -> Self {
+        // Continuing from the last logged event, or from the current time when it is older,
+        // keeps ids increasing across restarts
+        let last_id = log_file.as_mut().and_then(last_logged_id).unwrap_or(0);
+        EventLog {
+            capacity,
+            recent: Mutex::new(VecDeque::with_capacity(capacity)),
+            next_id: AtomicU64::new(unix_time_ms(SystemTime::now()).max(last_id + 1)),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            log_file: log_file.map(Mutex::new),
+        }
+    }
+
+    /// Receive every event recorded from now on
+    pub fn subscribe(&self) -> Receiver<ChangeEvent> {
+        self.events.subscribe()
+    }
+
+    /// Turn a cache change into a numbered event, keep it in the buffer, dropping the
+    /// oldest event once the buffer is full, and append it to the event log file if any.
+    /// Called by the cache for every change as it is made, see `Cache::with_recorder`.
+    /// First fills of a key are not changes and are not recorded.
+    pub fn record(&self, change: &CacheChange) -> Option<ChangeEvent> {
+        if change.first_fill {
+            return None;
+        }
+        let (endpoint, name) = split_cache_key(&change.key).unwrap_or((&change.key, ""));
+
+        let event = ChangeEvent {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            at: unix_time(change.at),
+            endpoint: endpoint.to_string(),
+            name: name.to_string(),
+            version: change.entry.version,
+            added: change.added.clone(),
+            removed: change.removed.clone(),
+        };
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == self.capacity {
+            recent.pop_front();
+        }
+        recent.push_back(event.clone());
+        drop(recent);
+
+        info!("Change to {}:{}: {} added, {} removed", event.endpoint, event.name, event.added.len(), event.removed.len());
+        if let Some(file) = &self.log_file {
+            let mut line = serde_json::to_vec(&event).unwrap();
+            line.push(b'\n');
+            if let Err(e) = file.lock().unwrap().write_all(&line) {
+                error!("Failed to write change event {} to the event log: {}", event.id, e);
+            }
+        }
+
+        // Sending only fails when nobody is subscribed
+        let _ = self.events.send(event.clone());
+        Some(event)
+    }
+
+    /// Up to `limit` buffered events with an id greater than `since`, optionally only for one endpoint
+    pub fn recent(&self, since: u64, endpoint: Option<&str>, limit: usize) -> Vec<ChangeEvent> {
+        self.recent.lock().unwrap()
+            .iter()
+            .filter(|event| event.id > since)
+            .filter(|event| endpoint.is_none_or(|endpoint| event.endpoint == endpoint))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Id of the last event in the event log file, `None` if it is empty or its last line
+/// is not a complete event
+fn last_logged_id(file: &mut File) -> Option<u64> {
+    #[derive(Deserialize)]
+    struct Logged {
+        id: u64,
+    }
+
+    // Read backwards from the end until the whole last line is in
+    let mut start = file.seek(SeekFrom::End(0)).ok()?;
+    let mut tail = Vec::new();
+    while start > 0 && !tail.trim_ascii_end().contains(&b'\n') {
+        let read = start.min(TAIL_CHUNK);
+        start -= read;
+        let mut chunk = vec![0; read as usize];
+        file.seek(SeekFrom::Start(start)).ok()?;
+        file.read_exact(&mut chunk).ok()?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+    }
+
+    let line = tail.trim_ascii_end().rsplit(|&byte| byte == b'\n').next()?;
+    serde_json::from_slice::<Logged>(line).ok().map(|logged| logged.id)
+}
+
+/// Open a log file for appending, creating it with mode 600
+pub fn open_log_file(path: &str, description: &str) -> Result<File, Box<dyn std::error::Error>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("Failed to open {} {}: {}", description, path, e))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::cache::{Cache, CacheEntry, cache_key};
+
+    #[test]
+    fn test_event_log() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+        let events = Arc::new(EventLog::new(2, Some(open_log_file(path.to_str().unwrap(), "event log").unwrap())));
+        let recorder = events.clone();
+        let cache = Cache::with_recorder(move |change| {
+            recorder.record(change);
+        });
+
+        // Filling the cache is not a change
+        cache.insert(cache_key("/group_members", "staff"), CacheEntry::new(vec!["alice".to_string()], vec![]));
+        cache.insert(cache_key("/group_members", "staff"), CacheEntry::new(vec!["alice".to_string()], vec![]));
+        assert!(events.recent(0, None, 10).is_empty());
+        for members in [vec!["alice", "bob"], vec!["bob"], vec!["bob", "carol"]] {
+            let members = members.into_iter().map(str::to_string).collect();
+            cache.insert(cache_key("/group_members", "staff"), CacheEntry::new(members, vec![]));
+        }
+
+        // Only the newest events stay buffered
+        let recent = events.recent(0, None, 10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[1].id, recent[0].id + 1);
+        assert_eq!(recent[0].removed, vec!["alice"]);
+        assert_eq!(recent[1].added, vec!["carol"]);
+        assert_eq!(events.recent(recent[0].id, None, 10).len(), 1);
+        assert!(events.recent(0, Some("/user_maildrop"), 10).is_empty());
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["endpoint"], "/group_members");
+        assert_eq!(lines[0]["name"], "staff");
+        assert_eq!(lines[0]["added"], serde_json::json!(["bob"]));
+        assert!(lines[1]["version"].as_u64() > lines[0]["version"].as_u64());
+        assert_eq!(lines[1]["removed"], serde_json::json!(["alice"]));
+        assert_eq!(lines[2]["id"], recent[1].id);
+
+        // Changes made faster than anyone reads them are all recorded
+        for i in 0..2 * EVENT_CAPACITY {
+            cache.insert(cache_key("/group_members", "staff"), CacheEntry::new(vec![i.to_string()], vec![]));
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3 + 2 * EVENT_CAPACITY);
+
+        // Ids keep increasing after a restart, even when the last run recorded events
+        // faster than the clock moved on
+        let last_id = events.recent(0, None, 2)[1].id;
+        assert!(last_id > unix_time_ms(SystemTime::now()));
+        let restarted = EventLog::new(2, Some(open_log_file(path.to_str().unwrap(), "event log").unwrap()));
+        assert_eq!(restarted.next_id.load(Ordering::Relaxed), last_id + 1);
+    }
+}
@@ -1,16 +1,35 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
 use axum::{
-    extract::{Path, State, Request},
-    response::Json,
-    routing::get,
-    Router,
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State, Request},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Router,
 };
-use log::{debug, info};
+use log::{debug, error, info, warn};
+use bytes::Bytes;
+use serde::{Deserialize, Deserializer};
 
 use crate::{
     AppState,
+    auth::{EndpointAccess, authenticated_client, require_access},
+    batch::batch_handler,
+    health::{healthz_handler, readyz_handler, status_handler},
+    metrics::{Metrics, metrics_handler, track_requests},
+    cache::{Cache, CacheEntry, Format, cache_key, unix_time},
     ldap::{connect_and_bind, query},
+    limits::rate_limit,
+    listener::{Peer, serve},
+    tls::{TlsState, run_tls_reload},
+    mirror::current_index,
+    response::{Envelope, Validators, conditional_response, negotiate, with_cache_status},
+    watch::watch_handler,
     config::{Config, EndpointConfig},
 };
 
@@ -18,26 +37,28 @@ use crate::{
 /// Used by both the handler and the refresh logic
 pub async fn execute_ldap_query(
     ldap: &mut ldap3::Ldap,
+    metrics: &Metrics,
     endpoint: &EndpointConfig,
     name: &str,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let filter_template = endpoint.search_filter();
-    let filter = format!("{}", filter_template.replace("{}", name));
+) -> Result<CacheEntry, Box<dyn std::error::Error>> {
+    let filter = endpoint.filter(name)?;
 
-    let values = query(ldap, endpoint.search_base(), endpoint.search_scope(), &filter, endpoint.attribute())
+    let result = query(ldap, metrics, endpoint.search_base(), endpoint.search_scope(), &filter, endpoint.attribute())
         .await?;
 
-    let mut final_result = values.clone();
+    let mut final_result = result.values.clone();
+    let mut dns = result.dns;
 
     // Apply result processing if configured
     if let Some(processing) = endpoint.result_processing() {
         match processing.r#type().as_str() {
             "dn_translation" => {
                 let mut processed_values = vec![];
-                for val in &values {
-                    let res = query(ldap, val, "base", "(objectClass=*)", processing.attribute())
+                for val in &result.values {
+                    let res = query(ldap, metrics, val, "base", "(objectClass=*)", processing.attribute())
                         .await?;
-                    processed_values.extend(res);
+                    processed_values.extend(res.values);
+                    dns.extend(res.dns);
                 }
                 final_result = processed_values;
             }
@@ -47,86 +68,590 @@ pub async fn execute_ldap_query(
         }
     }
 
-    Ok(final_result)
+    // LDAP returns values in no particular order, sorting them keeps a refresh that
+    // returns the same values in another order from looking like a change
+    final_result.sort_unstable();
+
+    Ok(CacheEntry::new(final_result, dns))
 }
 
-pub async fn start_server(config: Arc<Config>, app_state: Arc<AppState>) -> Result<(), Box<dyn std::error::Error>> {
-    info!("Starting web server on {}", config.server().bind_addr());
-    
-    let mut app = Router::new();
+/// Query LDAP for a name on a connection of its own
+async fn fetch_live(state: &AppState, endpoint: &EndpointConfig, name: &str) -> Result<CacheEntry, String> {
+    info!("Using endpoint: {} with search_base: {}", endpoint.path(), endpoint.search_base());
+
+    let mut ldap = connect_and_bind(state.config.ldap(), &state.metrics)
+        .await
+        .map_err(|e| format!("LDAP connect/bind failed: {}", e))?;
+
+    // Use the shared function to execute the LDAP query
+    execute_ldap_query(&mut ldap, &state.metrics, endpoint, name)
+        .await
+        .map_err(|e| format!("Failed to execute LDAP query: {}", e))
+}
+
+/// Cache a freshly fetched result, replacing any cached entry but keeping its statistics
+pub fn store(cache: &Cache, endpoint: &EndpointConfig, name: &str, mut result: CacheEntry) -> Arc<CacheEntry> {
+    let cache_key = cache_key(endpoint.path(), name);
+
+    if let Some(previous) = cache.get(&cache_key) {
+        result.carry_over_stats(&previous);
+    }
+    result.record_access();
+
+    info!("Cache populated for '{}' with {} results", cache_key, result.values.len());
+    cache.insert(cache_key, result)
+}
+
+/// Query LDAP for a name and cache the result, replacing any cached entry
+pub async fn fetch_and_cache(state: &AppState, endpoint: &EndpointConfig, name: &str) -> Result<Arc<CacheEntry>, String> {
+    let result = fetch_live(state, endpoint, name).await?;
+    Ok(store(&state.cache, endpoint, name, result))
+}
+
+/// Paths served by the daemon itself, which endpoints cannot use
+pub const RESERVED_PATHS: [&str; 4] = ["/healthz", "/readyz", "/status", "/metrics"];
+
+/// Routes of the daemon's own endpoints and of every configured endpoint
+fn router(config: &Arc<Config>, app_state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .route("/status", get(status_handler))
+        .route("/metrics", get(metrics_handler));
     
     // Dynamically create routes for all configured endpoints
+    let mut endpoints = Router::new();
     for endpoint in config.endpoints() {
-        info!("Adding route: {} -> generic_handler", endpoint.path());
-        app = app.route(&format!("{}/:name", endpoint.path()), get(generic_handler));
+        let endpoint_routes = endpoint.routes(config.server());
+        info!("Adding route: {} -> generic_handler", endpoint_routes.lookup);
+        let mut routes = Router::new().route(&endpoint_routes.lookup, get(generic_handler));
+        if let Some(batch) = &endpoint_routes.batch {
+            routes = routes.route(batch, post(batch_handler));
+        }
+        if let Some(watch) = &endpoint_routes.watch {
+            routes = routes.route(watch, get(watch_handler));
+        }
+        // Handlers get their endpoint from the route rather than from the request path
+        routes = routes.layer(Extension(Arc::new(endpoint.clone())));
+        // Endpoints with an allow list only answer the clients on it
+        if let Some(access) = EndpointAccess::new(config.clone(), endpoint) {
+            info!("Restricting {} to clients {}", endpoint.path(), access.allowed().join(", "));
+            routes = routes.route_layer(middleware::from_fn_with_state(access, require_access));
+        }
+        endpoints = endpoints.merge(routes);
     }
-    
-    let app = app.with_state(app_state);
 
-    let listener = tokio::net::TcpListener::bind(config.server().bind_addr()).await?;
-    info!("Server listening on {}", config.server().bind_addr());
-    
-    axum::serve(listener, app).await?;
+    // Rate limits apply to lookups, not to health checks and metrics
+    app.merge(endpoints.route_layer(middleware::from_fn_with_state(app_state.clone(), rate_limit)))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), track_requests))
+        .with_state(app_state)
+}
+
+pub async fn start_server(config: Arc<Config>, app_state: Arc<AppState>) -> Result<(), Box<dyn std::error::Error>> {
+    let listeners = config.server().listeners();
+    info!("Starting web server on {}", listeners.iter().map(|listen| listen.address().as_str()).collect::<Vec<_>>().join(", "));
+
+    let app = router(&config, app_state);
+
+    let tls = match config.server().tls() {
+        Some(tls_config) => {
+            let tls = Arc::new(TlsState::new(tls_config.clone())?);
+            tokio::spawn(run_tls_reload(tls.clone()));
+            Some(tls)
+        }
+        None => None,
+    };
+
+    // Serve the same routes on every listener, stopping at the first one that fails
+    futures::future::try_join_all(listeners.iter().map(|listen| serve(listen, tls.clone(), app.clone()))).await?;
     Ok(())
 }
 
+/// The name a request looks up: the last path segment for endpoints with the `{}`
+/// placeholder, or the lookup name of the values the path and the query string bind to
+/// named placeholders
+pub struct LookupName(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for LookupName {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(endpoint) = parts.extensions.get::<Arc<EndpointConfig>>().cloned() else {
+            error!("No endpoint attached to the route of {}", parts.uri.path());
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        };
+
+        if endpoint.placeholders().is_empty() {
+            let Path(name) = Path::<String>::from_request_parts(parts, state).await.map_err(IntoResponse::into_response)?;
+            return Ok(LookupName(name));
+        }
+
+        // Routes without parameters have no path parameters to extract
+        let path_params = if endpoint.path_params().is_empty() {
+            HashMap::new()
+        } else {
+            Path::<HashMap<String, String>>::from_request_parts(parts, state).await.map_err(IntoResponse::into_response)?.0
+        };
+        let Query(query) = Query::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let values: BTreeMap<&str, &str> = endpoint.path_params().into_iter()
+            .filter_map(|name| path_params.get(name).map(|value| (name, value.as_str())))
+            .chain(endpoint.query().iter().filter_map(|name| query.get(name).map(|value| (name.as_str(), value.as_str()))))
+            .collect();
+        endpoint.lookup_name(&values)
+            .map(LookupName)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())
+    }
+}
+
+/// Where the data of a response came from, reported in the `X-Cache` header
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Source {
+    Hit,
+    Miss,
+    Live,
+    Mirror,
+}
+
+impl Source {
+    fn as_str(self) -> &'static str {
+        match self {
+            Source::Hit => "HIT",
+            Source::Miss => "MISS",
+            Source::Live => "LIVE",
+            Source::Mirror => "MIRROR",
+        }
+    }
+}
+
+/// Accept `1`/`true` and `0`/`false` for flags in the query string
+fn deserialize_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    match Option::<String>::deserialize(deserializer)?.as_deref() {
+        None => Ok(None),
+        Some("1" | "true") => Ok(Some(true)),
+        Some("0" | "false") => Ok(Some(false)),
+        Some(other) => Err(serde::de::Error::custom(format!("invalid flag '{}', expected 1, 0, true or false", other))),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LookupParams {
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    refresh: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    envelope: Option<bool>,
+    format: Option<Format>,
+}
+
+/// Check whether the request asks to bypass the cache, by `?refresh=true` or `Cache-Control: no-cache`
+fn wants_live(params: &LookupParams, headers: &HeaderMap) -> bool {
+    params.refresh == Some(true) || headers.get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"))
+}
+
+/// Respond with the values of an entry in the negotiated format, wrapped in an envelope
+/// if requested and the format is JSON
+fn respond(
+    request_headers: &HeaderMap,
+    config: &Config,
+    endpoint: &EndpointConfig,
+    params: &LookupParams,
+    entry: &CacheEntry,
+    source: Source,
+) -> Response {
+    let max_age = endpoint.refresh_interval_secs(config.server());
+    let format = params.format.unwrap_or_else(|| negotiate(request_headers, *endpoint.format()));
+
+    // An envelope configured for the endpoint only applies to JSON, one asked for explicitly must be JSON
+    if format != Format::Json && params.envelope == Some(true) {
+        return (StatusCode::BAD_REQUEST, "envelope is only available in the json format").into_response();
+    }
+
+    let response = if format == Format::Json && params.envelope.unwrap_or(*endpoint.envelope()) {
+        let envelope = Envelope {
+            values: &entry.values,
+            count: entry.values.len(),
+            cached: matches!(source, Source::Hit | Source::Mirror),
+            fetched_at: unix_time(entry.fetched_at),
+            stale: entry.stale,
+            endpoint: endpoint.path(),
+            ldap_server: config.ldap().url(),
+        };
+        let body = Bytes::from(serde_json::to_vec(&envelope).unwrap());
+        // Tied to the values only, so weak: `cached`, `fetched_at` and `stale` change without the values changing
+        let validators = Validators {
+            etag: format!("{}-envelope", entry.content_hash()),
+            weak: true,
+            last_modified: entry.modified_at,
+            max_age,
+        };
+        conditional_response(request_headers, format.content_type(), body, &validators)
+    } else {
+        let etag = match format {
+            Format::Json => entry.content_hash().to_string(),
+            _ => format!("{}-{}", entry.content_hash(), format.name()),
+        };
+        let validators = Validators { etag, weak: false, last_modified: entry.modified_at, max_age };
+        conditional_response(request_headers, format.content_type(), entry.body(format), &validators)
+    };
+
+    with_cache_status(response, source.as_str())
+}
+
 pub async fn generic_handler(
-    Path(name): Path<String>,
     State(state): State<Arc<AppState>>,
+    Extension(endpoint): Extension<Arc<EndpointConfig>>,
+    LookupName(name): LookupName,
+    Query(params): Query<LookupParams>,
     request: Request,
-) -> Json<Vec<String>> {
-    let AppState { config, cache } = &*state;
+) -> Response {
+    let AppState { config, cache, mirrors, limits, .. } = &*state;
+    let endpoint: &EndpointConfig = &endpoint;
 
-    // Extract the endpoint path from the request
-    let path = request.uri().path();
-    // Split by '/' and get the first non-empty segment
-    let endpoint_path = path.split('/')
-        .filter(|s| !s.is_empty())
-        .next()
-        .unwrap_or("")
-        .to_string();
-    let full_endpoint_path = format!("/{}", endpoint_path);
+    info!("Received request for group '{}' on endpoint '{}'", name, endpoint.path());
+
+    // API clients with `live` set can bypass the cache and get the live directory value
+    if wants_live(&params, request.headers()) {
+        let peer = request.extensions().get::<Peer>().cloned().unwrap_or_default();
+        let authorized = authenticated_client(config.clients(), request.headers(), &peer).is_some_and(|client| *client.live());
+        if authorized {
+            info!("Live lookup of '{}' on '{}' requested, bypassing the cache", name, endpoint.path());
+            let _permit = match limits.acquire_miss().await {
+                Ok(permit) => permit,
+                Err(busy) => return busy.into_response(),
+            };
+            let result = if endpoint.mirror().is_some() {
+                // The mirror is only updated by rebuilds, so the live value is not stored
+                fetch_live(&state, endpoint, &name).await.map(Arc::new)
+            } else {
+                fetch_and_cache(&state, endpoint, &name).await
+            };
+            return match result {
+                Ok(entry) => respond(request.headers(), config, endpoint, &params, &entry, Source::Live),
+                Err(e) => {
+                    error!("Live lookup of '{}' on '{}' failed: {}", name, endpoint.path(), e);
+                    (StatusCode::BAD_GATEWAY, e).into_response()
+                }
+            };
+        }
+        if params.refresh == Some(true) {
+            warn!("Rejected unauthorized live lookup of '{}' on '{}'", name, endpoint.path());
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        // Clients and proxies send no-cache on their own, from other clients it is ignored
+        debug!("Ignoring unauthorized Cache-Control: no-cache for '{}' on '{}'", name, endpoint.path());
+    }
 
-    info!("Received request for group '{}' on endpoint '{}'", name, full_endpoint_path);
+    // Mirrored endpoints answer every name from their local copy once it has been built
+    if let Some(index) = current_index(mirrors, endpoint.path()) {
+        let values = index.get(&name).cloned().unwrap_or_default();
+        info!("Mirror lookup for '{}' on '{}', returning {} results", name, endpoint.path(), values.len());
+        let mut entry = CacheEntry::new(values, vec![]);
+        entry.fetched_at = index.built_at;
+        entry.modified_at = index.built_at;
+        return respond(request.headers(), config, endpoint, &params, &entry, Source::Mirror);
+    }
 
     // Create a unique cache key that includes both endpoint and name
-    let cache_key = format!("{}:{}", full_endpoint_path, name);
+    let cache_key = cache_key(endpoint.path(), &name);
 
     // Check cache first
     {
-        let cache_guard = cache.lock().unwrap();
-        if let Some(cached) = cache_guard.get(&cache_key) {
-            info!("Cache hit for '{}', returning {} cached results", cache_key, cached.len());
-            return Json(cached.clone());
+        if let Some(cached) = cache.get(&cache_key) {
+            cached.record_hit();
+            state.metrics.cache_hits.with_label_values(&[endpoint.path()]).inc();
+            info!("Cache hit for '{}', returning {} cached results", cache_key, cached.values.len());
+            return respond(request.headers(), config, endpoint, &params, &cached, Source::Hit);
         }
     }
 
     info!("Cache miss for '{}', querying LDAP", cache_key);
+    state.metrics.cache_misses.with_label_values(&[endpoint.path()]).inc();
 
-    // Find which endpoint this request is for by matching the request path
-    let endpoint = config.endpoints()
-        .iter()
-        .find(|ep| *ep.path() == full_endpoint_path)
-        .expect(&format!("No matching endpoint found for {}", full_endpoint_path));
-    
-    info!("Using endpoint: {} with search_base: {}", endpoint.path(), endpoint.search_base());
-    
-    // If not in cache, query LDAP
-    let mut ldap = connect_and_bind(config.ldap().url(), config.ldap().bind_dn(), config.ldap().bind_password())
-        .await
-        .expect("LDAP connect/bind failed");
+    // If not in cache, query LDAP once other misses leave room for it
+    let _permit = match limits.acquire_miss().await {
+        Ok(permit) => permit,
+        Err(busy) => return busy.into_response(),
+    };
+    match fetch_and_cache(&state, endpoint, &name).await {
+        Ok(entry) => respond(request.headers(), config, endpoint, &params, &entry, Source::Miss),
+        Err(e) => {
+            error!("Lookup of '{}' on '{}' failed: {}", name, endpoint.path(), e);
+            (StatusCode::BAD_GATEWAY, e).into_response()
+        }
+    }
+}
 
-    // Use the shared function to execute the LDAP query
-    let final_result = execute_ldap_query(&mut ldap, endpoint, &name)
-        .await
-        .expect("Failed to execute LDAP query");
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use axum::{body::Body, http::HeaderValue};
+    use tower::ServiceExt;
 
-    // Cache the result
-    {
-        let mut cache_guard = cache.lock().unwrap();
-        cache_guard.insert(cache_key.clone(), final_result.clone());
-        info!("Cache populated for '{}' with {} results", cache_key, final_result.len());
+    /// Router of a configuration without its `ldap` section, with the given values cached
+    /// by endpoint path and name. Nothing listens on the LDAP port, so lookups fail right away.
+    pub fn test_app(yaml: &str, cached: &[(&str, &str, &str)]) -> Router {
+        let state = test_state(yaml, cached);
+        router(&state.config.clone(), state)
+    }
+
+    /// State behind `test_app`
+    pub fn test_state(yaml: &str, cached: &[(&str, &str, &str)]) -> Arc<AppState> {
+        let config: Config = serde_yaml::from_str(&format!(r#"
+ldap:
+  url: "ldap://127.0.0.1:1"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+{}"#, yaml)).unwrap();
+        let cache = Cache::new();
+        for (path, name, value) in cached {
+            cache.insert(cache_key(path, name), CacheEntry::new(vec![value.to_string()], vec![]));
+        }
+        Arc::new(AppState::new(Arc::new(config), cache, Arc::new(crate::events::EventLog::new(10, None))))
+    }
+
+    pub fn get(uri: &str) -> Request {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_router_nested_paths() {
+        let app = test_app(r#"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+  path_prefix: "/ldap"
+endpoints:
+  - path: "/v1/groups"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={})"
+    search_scope: "subtree"
+    attribute: "member"
+  - path: "/v1/users"
+    search_base: "ou=people,dc=example,dc=com"
+    search_filter: "(uid={})"
+    search_scope: "subtree"
+    attribute: "mail"
+  - path: "/v1/orgs/{org}/users"
+    search_base: "ou=people,dc=example,dc=com"
+    search_filter: "(&(o={org})(uid={user}))"
+    search_scope: "subtree"
+    attribute: "mail"
+    query: ["user"]
+"#, &[("/v1/users", "alice", "alice@example.com"), ("/v1/orgs/{org}/users", "org=acme&user=bob", "bob@acme.com")]);
+
+        let response = app.clone().oneshot(get("/ldap/v1/users/alice")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], br#"["alice@example.com"]"#);
+
+        // The endpoint comes from the route, not from the first segment of the path
+        let batch = Request::builder()
+            .method("POST")
+            .uri("/ldap/v1/groups/_batch")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("[]"))
+            .unwrap();
+        assert_eq!(app.clone().oneshot(batch).await.unwrap().status(), StatusCode::OK);
+
+        // Named placeholders are bound from the path and the query string
+        let response = app.clone().oneshot(get("/ldap/v1/orgs/acme/users?user=bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], br#"["bob@acme.com"]"#);
+        assert_eq!(app.clone().oneshot(get("/ldap/v1/orgs/acme/users")).await.unwrap().status(), StatusCode::BAD_REQUEST);
+
+        // Endpoints are only served behind the prefix, the daemon's own routes are not
+        assert_eq!(app.clone().oneshot(get("/v1/users/alice")).await.unwrap().status(), StatusCode::NOT_FOUND);
+        assert_eq!(app.oneshot(get("/healthz")).await.unwrap().status(), StatusCode::OK);
     }
 
-    Json(final_result)
+    #[tokio::test]
+    async fn test_live_lookup_requires_live_client() {
+        use sha2::{Digest, Sha256};
+
+        let app = test_app(&format!(r#"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+admin:
+  bind_addr: "127.0.0.1:8081"
+  token: "a_long_random_admin_token"
+clients:
+  - name: "operator"
+    token_sha256: "{}"
+    live: true
+  - name: "portal"
+    token_sha256: "{}"
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={{}})"
+    search_scope: "subtree"
+    attribute: "member"
+"#, hex::encode(Sha256::digest(b"operator-token")), hex::encode(Sha256::digest(b"portal-token"))),
+            &[("/group_members", "staff", "alice")]);
+
+        let live = |token: &str| Request::builder()
+            .uri("/group_members/staff?refresh=true")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+
+        // The admin token is only good for the admin API
+        assert_eq!(app.clone().oneshot(live("a_long_random_admin_token")).await.unwrap().status(), StatusCode::FORBIDDEN);
+        assert_eq!(app.clone().oneshot(live("portal-token")).await.unwrap().status(), StatusCode::FORBIDDEN);
+        // The live client gets through to LDAP, which cannot be reached here
+        assert_eq!(app.clone().oneshot(live("operator-token")).await.unwrap().status(), StatusCode::BAD_GATEWAY);
+
+        // Cache misses report the failure too
+        assert_eq!(app.oneshot(get("/group_members/admins")).await.unwrap().status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_lookup() {
+        use crate::mirror::MirrorIndex;
+        use std::{collections::HashMap, time::SystemTime};
+
+        let state = test_state(r#"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={})"
+    search_scope: "subtree"
+    attribute: "member"
+    mirror: {}
+"#, &[("/group_members", "staff", "uid=cached")]);
+        let app = router(&state.config.clone(), state.clone());
+
+        // Until the first copy has been built, names are answered through the cache
+        let response = app.clone().oneshot(get("/group_members/staff")).await.unwrap();
+        assert_eq!(response.headers()["X-Cache"], "HIT");
+
+        let index = MirrorIndex {
+            built_at: SystemTime::now(),
+            entries: HashMap::from([("staff".to_string(), vec!["uid=alice".to_string(), "uid=bob".to_string()])]),
+        };
+        *state.mirrors["/group_members"].write().unwrap() = Some(Arc::new(index));
+
+        let response = app.clone().oneshot(get("/group_members/Staff")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["X-Cache"], "MIRROR");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], br#"["uid=alice","uid=bob"]"#);
+
+        // Names missing from the copy are answered from it too, without querying LDAP
+        let response = app.oneshot(get("/group_members/nobody")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["X-Cache"], "MIRROR");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"[]");
+    }
+
+    #[test]
+    fn test_wants_live() {
+        let mut headers = HeaderMap::new();
+        assert!(!wants_live(&LookupParams::default(), &headers));
+        assert!(wants_live(&LookupParams { refresh: Some(true), ..Default::default() }, &headers));
+
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("max-age=0, No-Cache"));
+        assert!(wants_live(&LookupParams::default(), &headers));
+
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        assert!(!wants_live(&LookupParams::default(), &headers));
+    }
+
+    #[test]
+    fn test_lookup_params() {
+        let parse = |query: &str| Query::<LookupParams>::try_from_uri(&format!("/group_members/staff?{}", query).parse().unwrap())
+            .map(|Query(params)| params);
+
+        let params = parse("refresh=1&envelope=false").unwrap();
+        assert_eq!(params.refresh, Some(true));
+        assert_eq!(params.envelope, Some(false));
+
+        let params = parse("").unwrap();
+        assert_eq!((params.refresh, params.envelope), (None, None));
+
+        assert!(parse("envelope=yes").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_respond_envelope() {
+        let config: Config = serde_yaml::from_str(r#"
+ldap:
+  url: "ldap://ldap.example.com:389"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={})"
+    search_scope: "subtree"
+    attribute: "member"
+"#).unwrap();
+        let endpoint = &config.endpoints()[0];
+        let entry = CacheEntry::new(vec!["alice".to_string(), "bob".to_string()], vec![]);
+
+        let response = respond(&HeaderMap::new(), &config, endpoint, &LookupParams::default(), &entry, Source::Hit);
+        assert_eq!(response.headers()["X-Cache"], "HIT");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], br#"["alice","bob"]"#);
+
+        let params = LookupParams { envelope: Some(true), ..Default::default() };
+        let response = respond(&HeaderMap::new(), &config, endpoint, &params, &entry, Source::Miss);
+        let etag = response.headers()[header::ETAG].clone();
+        assert_eq!(etag, format!("W/\"{}-envelope\"", entry.content_hash()));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let envelope: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(envelope["values"], serde_json::json!(["alice", "bob"]));
+        assert_eq!(envelope["count"], 2);
+        assert_eq!(envelope["cached"], false);
+        assert_eq!(envelope["stale"], false);
+        assert_eq!(envelope["endpoint"], "/group_members");
+        assert_eq!(envelope["ldap_server"], "ldap://ldap.example.com:389");
+
+        // The same values served from the cache are not modified
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let response = respond(&headers, &config, endpoint, &params, &entry, Source::Hit);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // A stale entry has a different envelope for the same values, so the tag is weak
+        let mut stale = entry.clone();
+        stale.stale = true;
+        let response = respond(&HeaderMap::new(), &config, endpoint, &params, &stale, Source::Hit);
+        assert_eq!(response.headers()[header::ETAG], etag);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let envelope: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(envelope["stale"], true);
+        assert!(etag.to_str().unwrap().starts_with("W/"));
+
+        // The bare values have a strong tag
+        let response = respond(&HeaderMap::new(), &config, endpoint, &LookupParams::default(), &entry, Source::Hit);
+        assert_eq!(response.headers()[header::ETAG], format!("\"{}\"", entry.content_hash()));
+
+        let params = LookupParams { format: Some(Format::Text), ..Default::default() };
+        let response = respond(&HeaderMap::new(), &config, endpoint, &params, &entry, Source::Hit);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert!(response.headers()[header::ETAG].to_str().unwrap().ends_with("-text\""));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"alice\nbob\n");
+
+        let params = LookupParams { format: Some(Format::Csv), envelope: Some(true), ..Default::default() };
+        let response = respond(&HeaderMap::new(), &config, endpoint, &params, &entry, Source::Hit);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
 }
@@ -0,0 +1,245 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use log::debug;
+use serde::Serialize;
+use tokio::time::{Duration, interval};
+
+use crate::{
+    AppState, RefreshSummary,
+    cache::unix_time,
+    config::Config,
+    ldap::connect_and_bind,
+};
+
+/// How often the LDAP server is probed with a bind when nothing else connects to it
+const PROBE_INTERVAL_SECS: u64 = 30;
+
+/// What the daemon knows about its own health at one point in time
+#[derive(Clone, Debug)]
+struct HealthState {
+    started_at: SystemTime,
+    preloaded: bool,
+    last_bind_ok: Option<SystemTime>,
+    /// Time and error of the last bind attempt, if it failed
+    last_bind_error: Option<(SystemTime, String)>,
+    last_refresh: Option<(SystemTime, RefreshSummary)>,
+    /// When the cache was last known to be up to date, by a refresh or by change tracking
+    cache_current_at: Option<SystemTime>,
+}
+
+/// What the daemon knows about its own health, updated by the tasks doing the work
+pub struct Health(Mutex<HealthState>);
+
+impl Default for Health {
+    fn default() -> Self {
+        Health(Mutex::new(HealthState {
+            started_at: SystemTime::now(),
+            preloaded: false,
+            last_bind_ok: None,
+            last_bind_error: None,
+            last_refresh: None,
+            cache_current_at: None,
+        }))
+    }
+}
+
+impl Health {
+    /// Start keeping track of the health of a daemon that starts now
+    pub fn new() -> Self {
+        Health::default()
+    }
+
+    /// Record the outcome of an LDAP connect and bind
+    pub fn record_bind(&self, error: Option<String>) {
+        let mut health = self.0.lock().unwrap();
+        match error {
+            None => {
+                health.last_bind_ok = Some(SystemTime::now());
+                health.last_bind_error = None;
+            }
+            Some(error) => health.last_bind_error = Some((SystemTime::now(), error)),
+        }
+    }
+
+    /// Record the outcome of a refresh cycle
+    pub fn record_refresh(&self, summary: &RefreshSummary) {
+        let mut health = self.0.lock().unwrap();
+        let now = SystemTime::now();
+        if summary.error.is_none() {
+            health.cache_current_at = Some(now);
+        }
+        health.last_refresh = Some((now, summary.clone()));
+    }
+
+    /// Record that change tracking keeps the cache up to date in place of a refresh cycle
+    pub fn record_cache_current(&self) {
+        self.0.lock().unwrap().cache_current_at = Some(SystemTime::now());
+    }
+
+    /// Record that the configured names have been preloaded
+    pub fn record_preloaded(&self) {
+        self.0.lock().unwrap().preloaded = true;
+    }
+
+    fn current(&self) -> HealthState {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Reasons the daemon is not ready to serve, empty when it is
+fn not_ready(health: &HealthState, now: SystemTime, max_refresh_age: Duration) -> Vec<String> {
+    let mut reasons = vec![];
+    if !health.preloaded {
+        reasons.push("cache preload has not finished".to_string());
+    }
+    if health.last_bind_ok.is_none() {
+        reasons.push("no successful LDAP bind yet".to_string());
+    }
+    // Before the first refresh cycle the cache is as current as it can be
+    let current_at = health.cache_current_at.unwrap_or(health.started_at);
+    let age = now.duration_since(current_at).unwrap_or_default();
+    if age > max_refresh_age {
+        reasons.push(format!("cache was last refreshed {}s ago", age.as_secs()));
+    }
+    reasons
+}
+
+fn max_refresh_age(config: &Config) -> Duration {
+    Duration::from_secs(config.server().refresh_interval_secs() * *config.server().ready_max_refresh_intervals() as u64)
+}
+
+/// Bind to LDAP at startup and then periodically, so reachability is known even when
+/// every lookup is answered from the cache
+pub async fn run_ldap_probe(state: Arc<AppState>) {
+    let mut interval = interval(Duration::from_secs(PROBE_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        match connect_and_bind(state.config.ldap(), &state.metrics).await {
+            Ok(mut ldap) => {
+                state.health.record_bind(None);
+                if let Err(e) = ldap.unbind().await {
+                    debug!("Failed to unbind LDAP probe connection: {}", e);
+                }
+            }
+            Err(e) => state.health.record_bind(Some(e.to_string())),
+        }
+    }
+}
+
+/// Liveness: the process is up and serving requests
+pub async fn healthz_handler() -> &'static str {
+    "ok\n"
+}
+
+/// Readiness: preload finished, LDAP was reached at least once and the cache has been
+/// refreshed recently enough
+pub async fn readyz_handler(State(state): State<Arc<AppState>>) -> Response {
+    let health = state.health.current();
+    let reasons = not_ready(&health, SystemTime::now(), max_refresh_age(&state.config));
+    if reasons.is_empty() {
+        return "ready\n".into_response();
+    }
+    (StatusCode::SERVICE_UNAVAILABLE, format!("not ready: {}\n", reasons.join(", "))).into_response()
+}
+
+#[derive(Debug, Serialize)]
+struct LdapStatus<'a> {
+    url: &'a str,
+    /// Whether the last bind attempt succeeded
+    reachable: bool,
+    last_bind_at: Option<u64>,
+    last_error: Option<String>,
+    last_error_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct RefreshStatus {
+    at: u64,
+    #[serde(flatten)]
+    summary: RefreshSummary,
+}
+
+#[derive(Debug, Serialize)]
+struct CacheStatus {
+    entries: usize,
+    bytes: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Status<'a> {
+    ready: bool,
+    not_ready: Vec<String>,
+    uptime_secs: u64,
+    preloaded: bool,
+    ldap: LdapStatus<'a>,
+    last_refresh: Option<RefreshStatus>,
+    cache: CacheStatus,
+}
+
+/// Detailed status for monitoring
+pub async fn status_handler(State(state): State<Arc<AppState>>) -> Response {
+    let AppState { config, cache, .. } = &*state;
+    let health = state.health.current();
+    let now = SystemTime::now();
+    let not_ready = not_ready(&health, now, max_refresh_age(config));
+
+    let status = Status {
+        ready: not_ready.is_empty(),
+        not_ready,
+        uptime_secs: now.duration_since(health.started_at).unwrap_or_default().as_secs(),
+        preloaded: health.preloaded,
+        ldap: LdapStatus {
+            url: config.ldap().url(),
+            reachable: health.last_bind_ok.is_some() && health.last_bind_error.is_none(),
+            last_bind_at: health.last_bind_ok.map(unix_time),
+            last_error_at: health.last_bind_error.as_ref().map(|(at, _)| unix_time(*at)),
+            last_error: health.last_bind_error.map(|(_, error)| error),
+        },
+        last_refresh: health.last_refresh.map(|(at, summary)| RefreshStatus { at: unix_time(at), summary }),
+        cache: CacheStatus {
+            entries: cache.len(),
+            bytes: cache.entries().iter().map(|(_, entry)| entry.size_bytes()).sum(),
+        },
+    };
+
+    Json(status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_not_ready() {
+        let started_at = SystemTime::now();
+        let max_age = Duration::from_secs(540);
+        let mut health = HealthState {
+            started_at,
+            preloaded: false,
+            last_bind_ok: None,
+            last_bind_error: Some((started_at, "Connection refused".to_string())),
+            last_refresh: None,
+            cache_current_at: None,
+        };
+        assert_eq!(not_ready(&health, started_at, max_age).len(), 2);
+
+        health.preloaded = true;
+        health.last_bind_ok = Some(started_at);
+        assert!(not_ready(&health, started_at + Duration::from_secs(540), max_age).is_empty());
+
+        // Refreshes that keep failing eventually make the daemon unready
+        let reasons = not_ready(&health, started_at + Duration::from_secs(600), max_age);
+        assert_eq!(reasons, vec!["cache was last refreshed 600s ago"]);
+
+        health.cache_current_at = Some(started_at + Duration::from_secs(500));
+        assert!(not_ready(&health, started_at + Duration::from_secs(600), max_age).is_empty());
+    }
+}
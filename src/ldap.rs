@@ -1,15 +1,29 @@
-use ldap3::{Ldap, LdapConnAsync, LdapError, Scope, SearchEntry};
+use ldap3::{
+    Ldap, LdapConnAsync, LdapError, Scope, SearchEntry,
+    adapters::{Adapter, EntriesOnly, PagedResults},
+};
 use log::{trace, warn};
+use std::time::Instant;
+
+use crate::{config::LdapConfig, metrics::Metrics};
 
 fn parse_scope(s: &str) -> Result<Scope, String> {
     match s.to_lowercase().as_str() {
         "base" => Ok(Scope::Base),
+        "one" => Ok(Scope::OneLevel),
         "subtree" => Ok(Scope::Subtree),
         _ => Err(format!("Invalid scope: {}", s)),
     }
 }
 
-pub async fn connect_and_bind(url: &str, bind_dn: &str, password: &str) -> Result<Ldap, LdapError> {
+pub async fn connect_and_bind(config: &LdapConfig, metrics: &Metrics) -> Result<Ldap, LdapError> {
+    let started = Instant::now();
+    let result = bind(config.url(), config.bind_dn(), config.bind_password()).await;
+    metrics.observe_ldap("bind", started, &result);
+    result
+}
+
+async fn bind(url: &str, bind_dn: &str, password: &str) -> Result<Ldap, LdapError> {
     trace!("Connecting to LDAP: {}", url);
 
     let (conn, mut ldap) = LdapConnAsync::new(url).await?;
@@ -22,15 +36,25 @@ pub async fn connect_and_bind(url: &str, bind_dn: &str, password: &str) -> Resul
     Ok(ldap)
 }
 
+/// Attribute values returned by a search, along with the DNs of the entries they came from
+pub struct QueryResult {
+    pub values: Vec<String>,
+    pub dns: Vec<String>,
+}
+
 pub async fn query(
     ldap: &mut Ldap,
+    metrics: &Metrics,
     base: &str,
     scope: &str,
     filter: &str,
     attr: &str,
-) -> Result<Vec<String>, LdapError> {
+) -> Result<QueryResult, LdapError> {
     trace!("Search for '{}' in base '{}' with scope '{}'", filter, base, scope);
-    let (results, _) = ldap.search(base, parse_scope(scope).unwrap(), filter, &[attr]).await?.success()?;
+    let started = Instant::now();
+    let result = ldap.search(base, parse_scope(scope).unwrap(), filter, &[attr]).await.and_then(|result| result.success());
+    metrics.observe_ldap("search", started, &result);
+    let (results, _) = result?;
     // We should probably do a better job of handing edge cases. program is only designed to work
     // when a single entry is found. If no entries are found we may want to 404 instead of
     // returning an empty list
@@ -42,13 +66,57 @@ pub async fn query(
 
 
     let mut values = vec![];
+    let mut dns = vec![];
 
     for result in results {
         let entry = SearchEntry::construct(result);
         if let Some(vals) = entry.attrs.get(attr) {
             values.extend(vals.clone());
         }
+        dns.push(entry.dn);
     }
 
-    Ok(values)
-}
\ No newline at end of file
+    Ok(QueryResult { values, dns })
+}
+
+/// Search for every entry matching `filter`, fetching results in pages of `page_size`
+/// so server size limits do not cut large result sets short
+pub async fn paged_search(
+    ldap: &mut Ldap,
+    metrics: &Metrics,
+    base: &str,
+    scope: &str,
+    filter: &str,
+    attrs: Vec<String>,
+    page_size: i32,
+) -> Result<Vec<SearchEntry>, LdapError> {
+    trace!("Paged search for '{}' in base '{}' with scope '{}'", filter, base, scope);
+    let started = Instant::now();
+    let result = run_paged_search(ldap, base, scope, filter, attrs, page_size).await;
+    metrics.observe_ldap("search", started, &result);
+    result
+}
+
+async fn run_paged_search(
+    ldap: &mut Ldap,
+    base: &str,
+    scope: &str,
+    filter: &str,
+    attrs: Vec<String>,
+    page_size: i32,
+) -> Result<Vec<SearchEntry>, LdapError> {
+    let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+        Box::new(EntriesOnly::new()),
+        Box::new(PagedResults::new(page_size)),
+    ];
+    let mut stream = ldap.streaming_search_with(adapters, base, parse_scope(scope).unwrap(), filter, attrs).await?;
+
+    let mut entries = vec![];
+    while let Some(entry) = stream.next().await? {
+        entries.push(SearchEntry::construct(entry));
+    }
+    stream.finish().await.success()?;
+
+    trace!("Paged search found {} entries", entries.len());
+    Ok(entries)
+}
@@ -0,0 +1,262 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use log::{debug, warn};
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::{Duration, interval, timeout},
+};
+
+use crate::{AppState, auth::authenticated_client, config::Config, listener::Peer};
+
+/// How often buckets of clients that stopped making requests are dropped
+const PRUNE_INTERVAL_SECS: u64 = 60;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token buckets of every client that made a request recently
+struct RateLimiter {
+    per_sec: f64,
+    burst: f64,
+    buckets: DashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    /// Take a token from the bucket of `client`, or tell how long until one is available
+    fn check(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let mut bucket = self.buckets.entry(client.to_string())
+            .or_insert_with(|| Bucket { tokens: self.burst, updated_at: now });
+        let elapsed = now.saturating_duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_sec).min(self.burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_sec))
+        }
+    }
+
+    /// Drop the buckets that have refilled completely, they are the same as new ones
+    fn prune(&self, now: Instant) {
+        let full_after = self.burst / self.per_sec;
+        self.buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated_at).as_secs_f64() < full_after);
+    }
+}
+
+/// Cache misses that query LDAP at the same time, and those waiting for their turn
+struct MissLimiter {
+    permits: Semaphore,
+    queued: AtomicUsize,
+    max_queued: usize,
+    queue_timeout: Duration,
+}
+
+/// Why a cache miss could not query LDAP
+#[derive(Debug, PartialEq)]
+pub enum Busy {
+    QueueFull,
+    Timeout,
+}
+
+/// 503 asking the client to come back shortly
+impl IntoResponse for Busy {
+    fn into_response(self) -> Response {
+        let message = match self {
+            Busy::QueueFull => "too many lookups waiting for LDAP",
+            Busy::Timeout => "timed out waiting for LDAP",
+        };
+        (StatusCode::SERVICE_UNAVAILABLE, [(header::RETRY_AFTER, "1")], message).into_response()
+    }
+}
+
+/// A cache miss counted as waiting for its turn until it is dropped, so a request that
+/// goes away while waiting, when its client disconnects, leaves the queue too
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Per-client rate limits and the global limit on concurrent LDAP lookups
+#[derive(Default)]
+pub struct Limits {
+    rate: Option<RateLimiter>,
+    misses: Option<MissLimiter>,
+}
+
+impl Limits {
+    pub fn new(config: &Config) -> Self {
+        let Some(limits) = config.server().limits() else {
+            return Limits::default();
+        };
+
+        let rate = limits.requests_per_sec().zip(limits.burst_size()).map(|(per_sec, burst)| RateLimiter {
+            per_sec,
+            burst: burst as f64,
+            buckets: DashMap::new(),
+        });
+        let misses = limits.max_concurrent_misses().map(|max| MissLimiter {
+            permits: Semaphore::new(max),
+            queued: AtomicUsize::new(0),
+            max_queued: *limits.max_queued_misses(),
+            queue_timeout: Duration::from_secs(*limits.queue_timeout_secs()),
+        });
+        Limits { rate, misses }
+    }
+
+    /// Wait for a turn to query LDAP for a cache miss. The permit, if any, must be held
+    /// until the query is done.
+    pub async fn acquire_miss(&self) -> Result<Option<SemaphorePermit<'_>>, Busy> {
+        let Some(misses) = &self.misses else {
+            return Ok(None);
+        };
+        if let Ok(permit) = misses.permits.try_acquire() {
+            return Ok(Some(permit));
+        }
+
+        let queued = misses.queued.fetch_add(1, Ordering::SeqCst);
+        let guard = Queued(&misses.queued);
+        if queued >= misses.max_queued {
+            warn!("Rejected a cache miss, {} lookups are already waiting for LDAP", misses.max_queued);
+            return Err(Busy::QueueFull);
+        }
+        let result = timeout(misses.queue_timeout, misses.permits.acquire()).await;
+        drop(guard);
+
+        match result {
+            Ok(permit) => Ok(Some(permit.expect("the semaphore is never closed"))),
+            Err(_) => {
+                warn!("Rejected a cache miss after waiting {}s for LDAP", misses.queue_timeout.as_secs());
+                Err(Busy::Timeout)
+            }
+        }
+    }
+}
+
+/// The key a client is rate limited by: its name when it authenticates as a configured
+/// client, otherwise its IP address or unix user id
+fn rate_key(config: &Config, request: &Request) -> String {
+    let peer = request.extensions().get::<Peer>().cloned().unwrap_or_default();
+    if let Some(client) = authenticated_client(config.clients(), request.headers(), &peer) {
+        return format!("client:{}", client.name());
+    }
+    match (peer.addr, peer.uid) {
+        (Some(addr), _) => addr.ip().to_string(),
+        (None, Some(uid)) => format!("uid:{}", uid),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+/// Answer 429 to clients that exceed their request rate
+pub async fn rate_limit(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(rate) = &state.limits.rate else {
+        return next.run(request).await;
+    };
+
+    let key = rate_key(&state.config, &request);
+    if let Err(wait) = rate.check(&key, Instant::now()) {
+        warn!("Rate limited {} on {}", key, request.uri().path());
+        let retry_after = wait.as_secs_f64().ceil().max(1.0).to_string();
+        return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry_after)]).into_response();
+    }
+    next.run(request).await
+}
+
+/// Periodically drop the rate limiting state of clients that went quiet
+pub async fn run_prune(state: Arc<AppState>) {
+    let Some(rate) = &state.limits.rate else {
+        return;
+    };
+    let mut interval = interval(Duration::from_secs(PRUNE_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        rate.prune(Instant::now());
+        debug!("Tracking request rates of {} clients", rate.buckets.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(yaml: &str) -> Limits {
+        let config: Config = serde_yaml::from_str(&format!(r#"
+ldap:
+  url: "ldap://ldap.example.com:389"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+  limits:
+{}
+endpoints: []
+"#, yaml)).unwrap();
+        Limits::new(&config)
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limits = limits("    requests_per_sec: 2\n    burst: 3");
+        let rate = limits.rate.as_ref().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(rate.check("10.0.0.1", now).is_ok());
+        }
+        assert_eq!(rate.check("10.0.0.1", now), Err(Duration::from_millis(500)));
+        // Other clients have their own bucket
+        assert!(rate.check("10.0.0.2", now).is_ok());
+        // Tokens come back at the configured rate
+        assert!(rate.check("10.0.0.1", now + Duration::from_millis(500)).is_ok());
+        assert!(rate.check("10.0.0.1", now + Duration::from_millis(500)).is_err());
+
+        rate.prune(now + Duration::from_millis(1600));
+        assert_eq!(rate.buckets.len(), 1);
+        rate.prune(now + Duration::from_secs(2));
+        assert!(rate.buckets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_miss() {
+        let limits = limits("    max_concurrent_misses: 1\n    max_queued_misses: 1\n    queue_timeout_secs: 1");
+        let permit = limits.acquire_miss().await.unwrap();
+        assert!(permit.is_some());
+
+        // One lookup may wait for the permit, the next one is turned away
+        let (queued, rejected) = tokio::join!(limits.acquire_miss(), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            limits.acquire_miss().await
+        });
+        assert_eq!(queued.err(), Some(Busy::Timeout));
+        assert_eq!(rejected.err(), Some(Busy::QueueFull));
+
+        // A lookup abandoned while waiting leaves the queue
+        assert!(timeout(Duration::from_millis(50), limits.acquire_miss()).await.is_err());
+        assert_eq!(limits.misses.as_ref().unwrap().queued.load(Ordering::SeqCst), 0);
+
+        drop(permit);
+        assert!(limits.acquire_miss().await.unwrap().is_some());
+        assert!(Limits::default().acquire_miss().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::Path,
+    sync::Arc,
+};
+
+use axum::{Router, http::Request};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use log::{debug, info};
+use nix::unistd::{Group, User};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+    time::{Duration, timeout},
+};
+use tower::ServiceExt;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::{config::ListenConfig, tls::TlsState};
+
+/// Connections that have not completed the TLS handshake by then are dropped
+const TLS_HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// Resolve a user given by name or numeric id
+fn resolve_uid(owner: &str) -> Result<u32, Box<dyn std::error::Error>> {
+    if let Ok(uid) = owner.parse() {
+        return Ok(uid);
+    }
+    let user = User::from_name(owner)?.ok_or_else(|| format!("Unknown user {}", owner))?;
+    Ok(user.uid.as_raw())
+}
+
+/// Resolve a group given by name or numeric id
+fn resolve_gid(group: &str) -> Result<u32, Box<dyn std::error::Error>> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let group_entry = Group::from_name(group)?.ok_or_else(|| format!("Unknown group {}", group))?;
+    Ok(group_entry.gid.as_raw())
+}
+
+/// Bind a unix domain socket at `path` with the given permissions, owner and group.
+///
+/// The socket is bound in a directory only this process can enter and moved into place
+/// once its permissions and ownership are set, so nobody can connect before that. A stale
+/// socket left behind by a previous run is replaced, anything else at `path` is left alone.
+fn bind_unix_socket(path: &str, mode: u32, owner: Option<&str>, group: Option<&str>) -> Result<UnixListener, Box<dyn std::error::Error>> {
+    if let Ok(metadata) = fs::symlink_metadata(path)
+        && !metadata.file_type().is_socket()
+    {
+        return Err(format!("Refusing to replace {} with a unix socket, it is not a socket", path).into());
+    }
+
+    let target = Path::new(path);
+    let file_name = target.file_name().ok_or_else(|| format!("Invalid unix socket path {}", path))?;
+    let private_dir = target.with_file_name(format!(".{}.{}", file_name.to_string_lossy(), std::process::id()));
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)
+        .map_err(|e| format!("Failed to create directory {} for unix socket {}: {}", private_dir.display(), path, e))?;
+
+    let result = bind_and_move(&private_dir.join(file_name), target, mode, owner, group);
+    let _ = fs::remove_dir_all(&private_dir);
+    result
+}
+
+/// Bind a unix domain socket at `staged`, set its permissions and ownership, then move it to `target`
+fn bind_and_move(staged: &Path, target: &Path, mode: u32, owner: Option<&str>, group: Option<&str>) -> Result<UnixListener, Box<dyn std::error::Error>> {
+    let listener = UnixListener::bind(staged)
+        .map_err(|e| format!("Failed to bind unix socket {}: {}", target.display(), e))?;
+    fs::set_permissions(staged, fs::Permissions::from_mode(mode))?;
+
+    if owner.is_some() || group.is_some() {
+        let uid = owner.map(resolve_uid).transpose()?;
+        let gid = group.map(resolve_gid).transpose()?;
+        std::os::unix::fs::chown(staged, uid, gid)
+            .map_err(|e| format!("Failed to change ownership of unix socket {}: {}", target.display(), e))?;
+    }
+
+    fs::rename(staged, target)
+        .map_err(|e| format!("Failed to move unix socket into place at {}: {}", target.display(), e))?;
+    Ok(listener)
+}
+
+/// What is known about the other end of a connection, added to the extensions of
+/// every request received on it
+#[derive(Clone, Debug, Default)]
+pub struct Peer {
+    /// Address of a TCP client
+    pub addr: Option<SocketAddr>,
+    /// Subject of the verified TLS client certificate
+    pub tls_subject: Option<String>,
+    /// User id of the process connected to a unix domain socket
+    pub uid: Option<u32>,
+}
+
+/// Subject of the first certificate presented by a TLS client, e.g. `CN=mail.example.com`
+fn certificate_subject(certificates: Option<&[tokio_rustls::rustls::pki_types::CertificateDer]>) -> Option<String> {
+    let certificate = certificates?.first()?;
+    match X509Certificate::from_der(certificate) {
+        Ok((_, certificate)) => Some(certificate.subject().to_string()),
+        Err(e) => {
+            debug!("Failed to parse TLS client certificate: {}", e);
+            None
+        }
+    }
+}
+
+/// Serve `app` on one accepted connection, over HTTP/1.1 or HTTP/2
+async fn serve_connection<I>(io: I, app: Router, peer: Peer)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let app = app.map_request(move |mut request: Request<_>| {
+        request.extensions_mut().insert(peer.clone());
+        request
+    });
+    let service = TowerToHyperService::new(app);
+    if let Err(e) = Builder::new(TokioExecutor::new())
+        .serve_connection_with_upgrades(TokioIo::new(io), service)
+        .await
+    {
+        debug!("Connection closed with error: {}", e);
+    }
+}
+
+/// Serve `app` on every connection accepted by a unix domain socket listener
+async fn serve_unix_listener(listener: UnixListener, app: Router) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let uid = match stream.peer_cred() {
+            Ok(credentials) => Some(credentials.uid()),
+            Err(e) => {
+                debug!("Failed to read unix socket peer credentials: {}", e);
+                None
+            }
+        };
+        tokio::spawn(serve_connection(stream, app.clone(), Peer { uid, ..Default::default() }));
+    }
+}
+
+/// Serve `app` on every connection accepted by a TCP listener
+async fn serve_tcp_listener(listener: TcpListener, app: Router) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        tokio::spawn(serve_connection(stream, app.clone(), Peer { addr: Some(addr), ..Default::default() }));
+    }
+}
+
+/// Serve `app` over TLS on every connection accepted by a TCP listener
+async fn serve_tls_listener(listener: TcpListener, tls: Arc<TlsState>, app: Router) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = tls.acceptor();
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            let stream = match timeout(Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SECS), acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake with {} timed out", peer);
+                    return;
+                }
+            };
+            let tls_subject = certificate_subject(stream.get_ref().1.peer_certificates());
+            serve_connection(stream, app, Peer { addr: Some(peer), tls_subject, uid: None }).await;
+        });
+    }
+}
+
+/// Serve `app` on a unix domain socket at `path` that is only accessible to its owner.
+pub async fn serve_unix_socket(path: &str, app: Router) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = bind_unix_socket(path, 0o600, None, None)?;
+    info!("Server listening on unix:{}", path);
+    serve_unix_listener(listener, app).await
+}
+
+/// Serve `app` on a configured listen address, TCP or unix domain socket. TCP listeners
+/// use TLS when it is configured, unix domain sockets never do.
+pub async fn serve(listen: &ListenConfig, tls: Option<Arc<TlsState>>, app: Router) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = listen.unix_path() {
+        let listener = bind_unix_socket(path, listen.mode_bits(), listen.owner().as_deref(), listen.group().as_deref())?;
+        info!("Server listening on unix:{} (mode {:o})", path, listen.mode_bits());
+        return serve_unix_listener(listener, app).await;
+    }
+
+    let addr = listen.tcp_addr().ok_or_else(|| format!("Invalid listen address {}", listen.address()))?;
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+    if let Some(tls) = tls {
+        info!("Server listening on {} (TLS)", addr);
+        return serve_tls_listener(listener, tls, app).await;
+    }
+
+    info!("Server listening on {}", addr);
+    serve_tcp_listener(listener, app).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Extension, routing::get};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_bind_unix_socket() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("api.sock");
+        let path = path.to_str().unwrap();
+
+        // A stale socket is replaced
+        drop(std::os::unix::net::UnixListener::bind(path).unwrap());
+        let listener = bind_unix_socket(path, 0o640, None, None).unwrap();
+        assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o640);
+        drop(listener);
+        // Nothing is left behind but the socket
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Other files are not
+        let file = dir.path().join("data");
+        fs::write(&file, "keep").unwrap();
+        assert!(bind_unix_socket(file.to_str().unwrap(), 0o600, None, None).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_serve_unix_socket() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("api.sock");
+        let listen: ListenConfig = serde_yaml::from_str(&format!(
+            "address: \"unix:{}\"\nmode: \"0660\"\ngroup: \"{}\"", path.display(), nix::unistd::getgid())).unwrap();
+
+        let app = Router::new().route("/uid", get(|Extension(peer): Extension<Peer>| async move { peer.uid.unwrap().to_string() }));
+        tokio::spawn(async move { serve(&listen, None, app).await.unwrap() });
+
+        let mut stream = loop {
+            match tokio::net::UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+
+        stream.write_all(b"GET /uid HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with(&nix::unistd::getuid().to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_serve_tls_with_client_certificates() {
+        use crate::tls::tests::{client_config, https_get, test_pki, tls_state, write_tls_config};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let pki = test_pki();
+        let tls = Arc::new(tls_state(write_tls_config(dir.path(), &pki, Some("required"))));
+
+        // Bind first to learn the port, then hand the address over to serve
+        let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let listen: ListenConfig = serde_yaml::from_str(&format!("address: \"{}\"", addr)).unwrap();
+        let app = Router::new().route("/subject", get(|Extension(peer): Extension<Peer>| async move { peer.tls_subject.unwrap() }));
+        tokio::spawn(async move { serve(&listen, Some(tls), app).await.unwrap() });
+
+        let response = loop {
+            match https_get(addr, client_config(&pki, true), "/subject").await {
+                Ok(response) => break response,
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => tokio::time::sleep(Duration::from_millis(10)).await,
+                Err(e) => panic!("{}", e),
+            }
+        };
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("CN=mail.example.com"));
+
+        // Without a client certificate the handshake is rejected
+        assert!(https_get(addr, client_config(&pki, false), "/subject").await.is_err());
+    }
+}
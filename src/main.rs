@@ -1,15 +1,38 @@
+mod admin;
+mod auth;
+mod batch;
+mod cache;
 mod config;
+mod dirsync;
+mod events;
+mod health;
 mod ldap;
+mod limits;
 mod handler;
+mod listener;
+mod metrics;
+mod mirror;
+mod preload;
+mod response;
+mod snapshot;
+mod sync;
+mod tls;
+mod watch;
+mod webhooks;
 
-use log::{error, info};
+use log::{debug, error, info};
+use serde::Serialize;
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Instant, SystemTime},
 };
 use tokio::time::{Duration, interval};
 
 use crate::{
+    cache::{Cache, RefreshOutcome, cache_key, split_cache_key},
     ldap::connect_and_bind,
     handler::{start_server, execute_ldap_query},
 };
@@ -17,18 +40,61 @@ use crate::{
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<config::Config>,
-    pub cache: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    pub cache: Cache,
+    pub mirrors: mirror::Mirrors,
+    pub events: Arc<events::EventLog>,
+    pub limits: Arc<limits::Limits>,
+    pub metrics: Arc<metrics::Metrics>,
+    pub health: Arc<health::Health>,
 }
 
-async fn refresh_cache(config: Arc<config::Config>, cache: Arc<Mutex<HashMap<String, Vec<String>>>>) {
+impl AppState {
+    pub fn new(config: Arc<config::Config>, cache: Cache, events: Arc<events::EventLog>) -> Self {
+        AppState {
+            mirrors: mirror::new_mirrors(&config),
+            limits: Arc::new(limits::Limits::new(&config)),
+            metrics: Arc::new(metrics::Metrics::new(config.ldap().url()).expect("metric definitions are valid")),
+            health: Arc::new(health::Health::new()),
+            config,
+            cache,
+            events,
+        }
+    }
+}
+
+/// Outcome of one `refresh_cache` run
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RefreshSummary {
+    pub refreshed: usize,
+    pub errors: usize,
+    pub error: Option<String>,
+}
+
+/// Record the outcome of a refresh cycle started at `started` for health checks and metrics
+fn finish_refresh(state: &AppState, started: Instant, summary: RefreshSummary) -> RefreshSummary {
+    state.health.record_refresh(&summary);
+    state.metrics.refresh_duration.observe(started.elapsed().as_secs_f64());
+    state.metrics.refresh_errors.inc_by(summary.errors as u64);
+    if summary.error.is_some() {
+        state.metrics.refresh_failures.inc();
+    }
+    summary
+}
+
+async fn refresh_cache(state: Arc<AppState>) -> RefreshSummary {
+    let AppState { config, cache, .. } = &*state;
     info!("Starting cache refresh cycle");
+    let started = Instant::now();
     
     // Connect to LDAP once for all refreshes
-    let mut ldap = match connect_and_bind(config.ldap().url(), config.ldap().bind_dn(), config.ldap().bind_password()).await {
+    let mut ldap = match connect_and_bind(config.ldap(), &state.metrics).await {
         Ok(ldap) => ldap,
         Err(e) => {
             error!("Failed to connect to LDAP for cache refresh: {}", e);
-            return;
+            return finish_refresh(&state, started, RefreshSummary {
+                error: Some(format!("Failed to connect to LDAP: {}", e)),
+                ..Default::default()
+            });
         }
     };
 
@@ -36,23 +102,16 @@ async fn refresh_cache(config: Arc<config::Config>, cache: Arc<Mutex<HashMap<Str
     let mut error_count = 0;
 
     // Get a copy of all cached keys to refresh
-    let keys_to_refresh: Vec<String> = {
-        let cache_guard = cache.lock().unwrap();
-        cache_guard.keys().cloned().collect()
-    };
+    let keys_to_refresh: Vec<String> = cache.keys();
 
     info!("Refreshing {} cached entries", keys_to_refresh.len());
 
     for cache_key in keys_to_refresh {
         // Parse the cache key to extract endpoint and name
-        let parts: Vec<&str> = cache_key.split(':').collect();
-        if parts.len() != 2 {
+        let Some((endpoint_path, name)) = split_cache_key(&cache_key) else {
             error!("Invalid cache key format: {}", cache_key);
             continue;
-        }
-        
-        let endpoint_path = parts[0];
-        let name = parts[1];
+        };
 
         // Find the matching endpoint configuration
         let endpoint = match config.endpoints().iter().find(|ep| ep.path() == endpoint_path) {
@@ -64,7 +123,7 @@ async fn refresh_cache(config: Arc<config::Config>, cache: Arc<Mutex<HashMap<Str
         };
 
         // Refresh this cached entry
-        match refresh_cached_entry(&mut ldap, endpoint, name, &cache).await {
+        match refresh_cached_entry(&mut ldap, &state, endpoint, name).await {
             Ok(_) => refresh_count += 1,
             Err(e) => {
                 error!("Failed to refresh cache for {}: {}", cache_key, e);
@@ -74,23 +133,43 @@ async fn refresh_cache(config: Arc<config::Config>, cache: Arc<Mutex<HashMap<Str
     }
 
     info!("Cache refresh completed: {} refreshed, {} errors", refresh_count, error_count);
+
+    finish_refresh(&state, started, RefreshSummary {
+        refreshed: refresh_count,
+        errors: error_count,
+        error: None,
+    })
 }
 
 async fn refresh_cached_entry(
     ldap: &mut ldap3::Ldap,
+    state: &AppState,
     endpoint: &crate::config::EndpointConfig,
     name: &str,
-    cache: &Arc<Mutex<HashMap<String, Vec<String>>>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let cache = &state.cache;
+    let cache_key = cache_key(endpoint.path(), name);
+
     // Use the shared function to execute the LDAP query
-    let final_result = execute_ldap_query(ldap, endpoint, name).await?;
+    let mut final_result = match execute_ldap_query(ldap, &state.metrics, endpoint, name).await {
+        Ok(result) => result,
+        Err(e) => {
+            // Keep serving the old data, but remember that it could not be refreshed
+            if let Some(entry) = cache.get(&cache_key) {
+                let mut entry = (*entry).clone();
+                entry.last_refresh = Some(RefreshOutcome { at: SystemTime::now(), error: Some(e.to_string()) });
+                cache.insert(cache_key, entry);
+            }
+            return Err(e);
+        }
+    };
 
     // Update the cache with fresh data
-    let cache_key = format!("{}:{}", endpoint.path(), name);
-    {
-        let mut cache_guard = cache.lock().unwrap();
-        cache_guard.insert(cache_key, final_result);
+    final_result.last_refresh = Some(RefreshOutcome { at: SystemTime::now(), error: None });
+    if let Some(previous) = cache.get(&cache_key) {
+        final_result.carry_over_stats(&previous);
     }
+    cache.insert(cache_key, final_result);
 
     Ok(())
 }
@@ -105,15 +184,68 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
     let config = Arc::new(config::Config::get_config()?);
-    let cache = Arc::new(Mutex::new(HashMap::new()));
-    let app_state = Arc::new(AppState {
-        config: config.clone(),
-        cache: cache.clone(),
+
+    // Record every change to cached values as it is made
+    let events_config = config.server().events().clone().unwrap_or_default();
+    let event_log_file = match events_config.log_file() {
+        Some(path) => Some(events::open_log_file(path, "event log")?),
+        None => None,
+    };
+    let events = Arc::new(events::EventLog::new(*events_config.buffer_size(), event_log_file));
+    let recorder = events.clone();
+    let cache = Cache::with_recorder(move |change| {
+        recorder.record(change);
     });
 
+    let sync_active = Arc::new(AtomicBool::new(false));
+    let app_state = Arc::new(AppState::new(config.clone(), cache.clone(), events.clone()));
+    tokio::spawn(limits::run_prune(app_state.clone()));
+
+    let dead_letter_file = match events_config.dead_letter_file() {
+        Some(path) => Some(events::open_log_file(path, "dead letter log")?),
+        None => None,
+    };
+    tokio::spawn(webhooks::run_webhooks(config.clone(), events.subscribe(), dead_letter_file));
+
+    // Keep track of whether LDAP can be reached, for the readiness and status routes
+    tokio::spawn(health::run_ldap_probe(app_state.clone()));
+
+    // Start mirroring the search base of every mirrored endpoint
+    for endpoint in config.endpoints().iter().filter(|ep| ep.mirror().is_some()) {
+        tokio::spawn(mirror::run_mirror(app_state.clone(), endpoint.clone()));
+    }
+
+    // Warm the cache from the last snapshot, served as stale until refreshed
+    if let Some(snapshot_config) = config.server().snapshot() {
+        match snapshot::load_snapshot(&config, snapshot_config, &cache) {
+            Ok(0) => (),
+            Ok(_) => {
+                tokio::spawn(refresh_cache(app_state.clone()));
+            }
+            Err(e) => error!("Ignoring cache snapshot {}: {}", snapshot_config.path(), e),
+        }
+        tokio::spawn(snapshot::run_snapshot_writer(snapshot_config.clone(), cache.clone()));
+    }
+
+    // Start change tracking if configured, polling takes over whenever it is unavailable
+    if let Some(tracking) = config.ldap().change_tracking() {
+        info!("Starting {} change tracking on '{}'", tracking.r#type(), tracking.search_base());
+        match tracking.r#type().as_str() {
+            "syncrepl" => {
+                tokio::spawn(sync::run_syncrepl(app_state.clone(), sync_active.clone()));
+            }
+            "dirsync" | "usn_changed" => {
+                tokio::spawn(dirsync::run_dirsync(app_state.clone(), sync_active.clone()));
+            }
+            other => {
+                error!("Unknown change tracking type: {}", other);
+            }
+        }
+    }
+
     // Start the background cache refresh thread
-    let refresh_config = config.clone();
-    let refresh_cache_arc = cache.clone();
+    let refresh_state = app_state.clone();
+    let refresh_sync_active = sync_active.clone();
     let refresh_interval = Duration::from_secs(*config.server().refresh_interval_secs());
     
     info!("Starting background cache refresh thread with interval: {} seconds", config.server().refresh_interval_secs());
@@ -125,13 +257,33 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         
         loop {
             interval.tick().await;
-            refresh_cache(refresh_config.clone(), refresh_cache_arc.clone()).await;
+            if refresh_sync_active.load(Ordering::SeqCst) {
+                debug!("Change tracking is active, skipping polling refresh cycle");
+                refresh_state.health.record_cache_current();
+                continue;
+            }
+            refresh_cache(refresh_state.clone()).await;
         }
     });
 
+    // Populate configured names in the background, the daemon is not ready until they are cached
+    let preload_state = app_state.clone();
+    tokio::spawn(async move {
+        preload::preload_cache(&preload_state).await;
+        preload_state.health.record_preloaded();
+    });
+
+    // Start the admin server alongside the web server if configured
+    let admin_server = async {
+        match config.admin() {
+            Some(admin_config) => admin::start_admin_server(admin_config.clone(), app_state.clone()).await,
+            None => Ok(()),
+        }
+    };
+
     // Start the web server
     info!("Starting web server...");
-    start_server(config, app_state).await?;
+    tokio::try_join!(start_server(config.clone(), app_state.clone()), admin_server)?;
 
     Ok(())
 }
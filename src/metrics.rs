@@ -0,0 +1,164 @@
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::error;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+use crate::AppState;
+
+/// Buckets of LDAP operation and request latencies, in seconds
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+/// Buckets of refresh cycle durations, in seconds
+const REFRESH_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+/// Prometheus metrics of the daemon, shared through `AppState`
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub cache_hits: IntCounterVec,
+    pub cache_misses: IntCounterVec,
+    pub cache_evictions: IntCounterVec,
+    cache_entries: IntGauge,
+    cache_bytes: IntGauge,
+    ldap_duration: HistogramVec,
+    ldap_errors: IntCounterVec,
+    pub refresh_duration: Histogram,
+    pub refresh_errors: IntCounter,
+    pub refresh_failures: IntCounter,
+    /// The daemon talks to a single LDAP server, whose URL labels the LDAP metrics
+    ldap_server: String,
+}
+
+impl Metrics {
+    pub fn new(ldap_server: &str) -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("ldap_cache".to_string()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"), &["route", "status"])?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time to answer HTTP requests by route")
+                .buckets(LATENCY_BUCKETS.to_vec()), &["route"])?;
+        let cache_hits = IntCounterVec::new(
+            Opts::new("cache_hits_total", "Lookups answered from the cache by endpoint"), &["endpoint"])?;
+        let cache_misses = IntCounterVec::new(
+            Opts::new("cache_misses_total", "Lookups not found in the cache by endpoint"), &["endpoint"])?;
+        let cache_evictions = IntCounterVec::new(
+            Opts::new("cache_evictions_total", "Entries removed from the cache by reason"), &["reason"])?;
+        let cache_entries = IntGauge::new("cache_entries", "Entries in the cache")?;
+        let cache_bytes = IntGauge::new("cache_bytes", "Approximate memory used by cached values")?;
+        let ldap_duration = HistogramVec::new(
+            HistogramOpts::new("ldap_operation_duration_seconds", "Latency of LDAP operations by server and operation")
+                .buckets(LATENCY_BUCKETS.to_vec()), &["server", "operation"])?;
+        let ldap_errors = IntCounterVec::new(
+            Opts::new("ldap_errors_total", "Failed LDAP operations by server and operation"), &["server", "operation"])?;
+        let refresh_duration = Histogram::with_opts(
+            HistogramOpts::new("refresh_duration_seconds", "Duration of cache refresh cycles")
+                .buckets(REFRESH_BUCKETS.to_vec()))?;
+        let refresh_errors = IntCounter::new("refresh_errors_total", "Cached entries that failed to refresh")?;
+        let refresh_failures = IntCounter::new("refresh_failures_total", "Refresh cycles that could not connect to LDAP")?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(cache_hits.clone()))?;
+        registry.register(Box::new(cache_misses.clone()))?;
+        registry.register(Box::new(cache_evictions.clone()))?;
+        registry.register(Box::new(cache_entries.clone()))?;
+        registry.register(Box::new(cache_bytes.clone()))?;
+        registry.register(Box::new(ldap_duration.clone()))?;
+        registry.register(Box::new(ldap_errors.clone()))?;
+        registry.register(Box::new(refresh_duration.clone()))?;
+        registry.register(Box::new(refresh_errors.clone()))?;
+        registry.register(Box::new(refresh_failures.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            cache_hits,
+            cache_misses,
+            cache_evictions,
+            cache_entries,
+            cache_bytes,
+            ldap_duration,
+            ldap_errors,
+            refresh_duration,
+            refresh_errors,
+            refresh_failures,
+            ldap_server: ldap_server.to_string(),
+        })
+    }
+
+    /// Record the latency and outcome of an LDAP operation started at `started`
+    pub fn observe_ldap<T, E>(&self, operation: &str, started: Instant, result: &Result<T, E>) {
+        let server = self.ldap_server.as_str();
+        self.ldap_duration.with_label_values(&[server, operation]).observe(started.elapsed().as_secs_f64());
+        if result.is_err() {
+            self.ldap_errors.with_label_values(&[server, operation]).inc();
+        }
+    }
+
+    /// Render every metric in the Prometheus text format
+    fn encode(&self) -> Result<Vec<u8>, prometheus::Error> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// Count every request and its latency by the route it matched
+pub async fn track_requests(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let route = request.extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| "unmatched".to_string(), |path| path.as_str().to_string());
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    state.metrics.http_request_duration.with_label_values(&[&route]).observe(started.elapsed().as_secs_f64());
+    state.metrics.http_requests.with_label_values(&[&route, response.status().as_str()]).inc();
+    response
+}
+
+/// Expose the metrics for scraping
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> Response {
+    let metrics = &state.metrics;
+    let entries = state.cache.entries();
+    metrics.cache_entries.set(entries.len() as i64);
+    metrics.cache_bytes.set(entries.iter().map(|(_, entry)| entry.size_bytes()).sum::<usize>() as i64);
+
+    match metrics.encode() {
+        Ok(body) => ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response(),
+        Err(e) => {
+            error!("Failed to encode metrics: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let metrics = Metrics::new("ldap://ldap.example.com:389").unwrap();
+        metrics.cache_hits.with_label_values(&["/group_members"]).inc();
+        let started = Instant::now();
+        metrics.observe_ldap("bind", started, &Err::<(), _>("refused"));
+        metrics.observe_ldap("search", started, &Ok::<_, ()>(()));
+
+        let text = String::from_utf8(metrics.encode().unwrap()).unwrap();
+        assert!(text.contains("ldap_cache_cache_hits_total{endpoint=\"/group_members\"} 1"));
+        assert!(text.contains("ldap_cache_ldap_errors_total{operation=\"bind\",server=\"ldap://ldap.example.com:389\"} 1"));
+        assert!(text.contains("ldap_cache_ldap_operation_duration_seconds_count{operation=\"search\",server=\"ldap://ldap.example.com:389\"} 1"));
+        assert!(!text.contains("ldap_cache_ldap_errors_total{operation=\"search\""));
+    }
+}
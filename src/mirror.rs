@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use ldap3::SearchEntry;
+use log::{error, info, warn};
+use tokio::time::{Duration, interval};
+
+use crate::{
+    AppState,
+    config::{Config, EndpointConfig},
+    ldap::{connect_and_bind, paged_search, query},
+    metrics::Metrics,
+};
+
+/// Local copy of an endpoint's search base, indexed by the lowercased value of its name attribute
+#[derive(Debug)]
+pub struct MirrorIndex {
+    pub built_at: SystemTime,
+    pub entries: HashMap<String, Vec<String>>,
+}
+
+impl MirrorIndex {
+    /// Look up a name, `None` if no entry under the search base has it
+    pub fn get(&self, name: &str) -> Option<&Vec<String>> {
+        self.entries.get(&name.to_lowercase())
+    }
+}
+
+/// The current index of every mirrored endpoint, keyed by endpoint path.
+/// Each index is replaced as a whole once a new copy has been built, so readers only
+/// ever see complete snapshots.
+pub type Mirrors = Arc<HashMap<String, RwLock<Option<Arc<MirrorIndex>>>>>;
+
+pub fn new_mirrors(config: &Config) -> Mirrors {
+    Arc::new(config.endpoints()
+        .iter()
+        .filter(|ep| ep.mirror().is_some())
+        .map(|ep| (ep.path().clone(), RwLock::new(None)))
+        .collect())
+}
+
+/// Get the current index of a mirrored endpoint, `None` if the endpoint is not mirrored
+/// or its first copy has not been built yet
+pub fn current_index(mirrors: &Mirrors, endpoint_path: &str) -> Option<Arc<MirrorIndex>> {
+    mirrors.get(endpoint_path)?.read().unwrap().clone()
+}
+
+/// Download every entry under the endpoint's search base and index it by name
+async fn build_index(ldap: &mut ldap3::Ldap, metrics: &Metrics, endpoint: &EndpointConfig) -> Result<MirrorIndex, Box<dyn std::error::Error>> {
+    let mirror = endpoint.mirror().as_ref().ok_or("endpoint is not mirrored")?;
+    let name_attribute = endpoint.name_attribute()
+        .ok_or("search_filter has no attribute to index")?;
+    let filter = endpoint.search_filter().replace("{}", "*");
+
+    let entries = paged_search(ldap, metrics, endpoint.search_base(), endpoint.search_scope(), &filter,
+        vec![name_attribute.to_string(), endpoint.attribute().clone()], *mirror.page_size()).await?;
+
+    // Resolve every referenced DN once per build rather than once per entry
+    let mut translations: HashMap<String, Vec<String>> = HashMap::new();
+    if let Some(processing) = endpoint.result_processing() {
+        for entry in &entries {
+            for dn in entry.attrs.get(endpoint.attribute()).into_iter().flatten() {
+                if translations.contains_key(dn) {
+                    continue;
+                }
+                let res = query(ldap, metrics, dn, "base", "(objectClass=*)", processing.attribute()).await?;
+                translations.insert(dn.clone(), res.values);
+            }
+        }
+    }
+
+    Ok(MirrorIndex {
+        built_at: SystemTime::now(),
+        entries: index_entries(endpoint, name_attribute, entries, &translations),
+    })
+}
+
+/// Index the downloaded entries by the lowercased values of `name_attribute`, replacing
+/// referenced DNs with their `translations` when the endpoint processes its results.
+/// Values are sorted like those of a lookup, so both answer a name with the same body.
+fn index_entries(
+    endpoint: &EndpointConfig,
+    name_attribute: &str,
+    entries: Vec<SearchEntry>,
+    translations: &HashMap<String, Vec<String>>,
+) -> HashMap<String, Vec<String>> {
+    let mut index: HashMap<String, Vec<String>> = HashMap::new();
+    for SearchEntry { mut attrs, .. } in entries {
+        let mut values = attrs.remove(endpoint.attribute()).unwrap_or_default();
+        if endpoint.result_processing().is_some() {
+            values = values.iter()
+                .flat_map(|dn| translations.get(dn).cloned().unwrap_or_default())
+                .collect();
+        }
+
+        for name in attrs.remove(name_attribute).unwrap_or_default() {
+            index.entry(name.to_lowercase()).or_default().extend(values.iter().cloned());
+        }
+    }
+    for values in index.values_mut() {
+        values.sort_unstable();
+        values.dedup();
+    }
+    index
+}
+
+/// Rebuild the mirror of one endpoint at startup and then every refresh interval,
+/// swapping in each new copy once it is complete. A failed rebuild keeps the previous copy.
+pub async fn run_mirror(state: Arc<AppState>, endpoint: EndpointConfig) {
+    let AppState { config, mirrors, metrics, .. } = &*state;
+    if endpoint.mirror().is_none() {
+        return;
+    }
+    let mut interval = interval(Duration::from_secs(endpoint.refresh_interval_secs(config.server())));
+
+    loop {
+        interval.tick().await;
+        info!("Building mirror of {} for {}", endpoint.search_base(), endpoint.path());
+
+        let result = match connect_and_bind(config.ldap(), metrics).await {
+            Ok(mut ldap) => build_index(&mut ldap, metrics, &endpoint).await,
+            Err(e) => Err(e.into()),
+        }
+        .map_err(|e| e.to_string());
+
+        match result {
+            Ok(index) => {
+                info!("Mirror of {} rebuilt with {} names", endpoint.path(), index.entries.len());
+                if let Some(slot) = mirrors.get(endpoint.path()) {
+                    *slot.write().unwrap() = Some(Arc::new(index));
+                }
+            }
+            Err(e) => {
+                if current_index(mirrors, endpoint.path()).is_some() {
+                    warn!("Failed to rebuild mirror of {}, keeping previous copy: {}", endpoint.path(), e);
+                } else {
+                    error!("Failed to build mirror of {}, answering from LDAP until it succeeds: {}", endpoint.path(), e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(result_processing: &str) -> Config {
+        serde_yaml::from_str(&format!(r#"
+ldap:
+  url: "ldap://localhost:389"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={{}})"
+    search_scope: "subtree"
+    attribute: "member"
+    mirror: {{}}
+{}
+"#, result_processing)).unwrap()
+    }
+
+    fn search_entry(dn: &str, attrs: &[(&str, &[&str])]) -> SearchEntry {
+        SearchEntry {
+            dn: dn.to_string(),
+            attrs: attrs.iter()
+                .map(|(name, values)| (name.to_string(), values.iter().map(|value| value.to_string()).collect()))
+                .collect(),
+            bin_attrs: HashMap::new(),
+        }
+    }
+
+    fn entries() -> Vec<SearchEntry> {
+        vec![
+            search_entry("cn=Staff,ou=groups,dc=example,dc=com", &[
+                ("cn", &["Staff", "Employees"]),
+                ("member", &["uid=carol,ou=users,dc=example,dc=com", "uid=alice,ou=users,dc=example,dc=com"]),
+            ]),
+            search_entry("cn=staff,ou=archive,ou=groups,dc=example,dc=com", &[
+                ("cn", &["staff"]),
+                ("member", &["uid=bob,ou=users,dc=example,dc=com", "uid=alice,ou=users,dc=example,dc=com"]),
+            ]),
+            search_entry("cn=empty,ou=groups,dc=example,dc=com", &[("cn", &["empty"])]),
+        ]
+    }
+
+    #[test]
+    fn test_index_entries() {
+        let config = test_config("");
+        let endpoint = &config.endpoints()[0];
+        let index = MirrorIndex {
+            built_at: SystemTime::now(),
+            entries: index_entries(endpoint, endpoint.name_attribute().unwrap(), entries(), &HashMap::new()),
+        };
+
+        // Names are matched case-insensitively, entries sharing a name are merged, and values
+        // are sorted without duplicates like those of a lookup
+        let staff = vec![
+            "uid=alice,ou=users,dc=example,dc=com".to_string(),
+            "uid=bob,ou=users,dc=example,dc=com".to_string(),
+            "uid=carol,ou=users,dc=example,dc=com".to_string(),
+        ];
+        assert_eq!(index.get("STAFF"), Some(&staff));
+        assert_eq!(index.get("staff"), Some(&staff));
+        assert_eq!(index.get("employees").map(Vec::len), Some(2));
+        assert_eq!(index.get("empty"), Some(&vec![]));
+        assert_eq!(index.get("missing"), None);
+    }
+
+    #[test]
+    fn test_index_entries_translated() {
+        let config = test_config(r#"
+    result_processing:
+      type: "dn_translation"
+      attribute: "uid""#);
+        let endpoint = &config.endpoints()[0];
+        let translations = HashMap::from([
+            ("uid=alice,ou=users,dc=example,dc=com".to_string(), vec!["alice".to_string()]),
+            ("uid=bob,ou=users,dc=example,dc=com".to_string(), vec!["bob".to_string()]),
+        ]);
+        let index = index_entries(endpoint, "cn", entries(), &translations);
+
+        // DNs without a translation are dropped
+        assert_eq!(index["staff"], vec!["alice", "bob"]);
+        assert_eq!(index["employees"], vec!["alice"]);
+    }
+
+    #[test]
+    fn test_current_index() {
+        let config = test_config("");
+        let mirrors = new_mirrors(&config);
+
+        // Nothing is served until the first copy has been built
+        assert!(current_index(&mirrors, "/group_members").is_none());
+        assert!(current_index(&mirrors, "/other").is_none());
+
+        let index = Arc::new(MirrorIndex { built_at: SystemTime::now(), entries: HashMap::new() });
+        *mirrors["/group_members"].write().unwrap() = Some(index.clone());
+        assert!(current_index(&mirrors, "/group_members").is_some_and(|current| Arc::ptr_eq(&current, &index)));
+    }
+}
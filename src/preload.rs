@@ -0,0 +1,133 @@
+use std::{collections::HashSet, fs};
+
+use log::{error, info, warn};
+
+use crate::{
+    AppState,
+    cache::cache_key,
+    config::{EndpointConfig, PreloadConfig},
+    ldap::{connect_and_bind, paged_search},
+    metrics::Metrics,
+};
+
+const ENUMERATE_PAGE_SIZE: i32 = 500;
+
+/// Read names from a preload file, one per line, ignoring blank lines and `#` comments
+fn read_names_file(path: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read preload file {}: {}", path, e))?;
+
+    Ok(content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Find every name the endpoint can answer for, by running its search filter with a
+/// wildcard in place of the placeholder
+async fn enumerate_names(ldap: &mut ldap3::Ldap, metrics: &Metrics, endpoint: &EndpointConfig) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let name_attribute = endpoint.name_attribute()
+        .ok_or("search_filter has no attribute to enumerate")?;
+    let filter = endpoint.search_filter().replace("{}", "*");
+
+    let entries = paged_search(ldap, metrics, endpoint.search_base(), endpoint.search_scope(), &filter,
+        vec![name_attribute.to_string()], ENUMERATE_PAGE_SIZE).await?;
+
+    Ok(entries.into_iter()
+        .filter_map(|mut entry| entry.attrs.remove(name_attribute))
+        .flatten()
+        .collect())
+}
+
+/// Collect the names to preload from every configured source, without duplicates
+async fn preload_names(
+    ldap: &mut ldap3::Ldap,
+    metrics: &Metrics,
+    endpoint: &EndpointConfig,
+    preload: &PreloadConfig,
+) -> Vec<String> {
+    let mut names = preload.names().clone();
+
+    if let Some(file) = preload.file() {
+        match read_names_file(file) {
+            Ok(file_names) => names.extend(file_names),
+            Err(e) => error!("Endpoint {}: {}", endpoint.path(), e),
+        }
+    }
+
+    if *preload.enumerate() {
+        match enumerate_names(ldap, metrics, endpoint).await {
+            Ok(found) => {
+                info!("Enumerated {} names for {}", found.len(), endpoint.path());
+                names.extend(found);
+            }
+            Err(e) => error!("Failed to enumerate names for {}: {}", endpoint.path(), e),
+        }
+    }
+
+    let mut seen = HashSet::new();
+    names.retain(|name| seen.insert(name.clone()));
+    names
+}
+
+/// Populate the cache with every configured preload name, before the daemon reports ready.
+/// Names that are already cached (e.g. from a snapshot) are left to the regular refresh.
+pub async fn preload_cache(state: &AppState) {
+    let AppState { config, cache, metrics, .. } = state;
+    if config.endpoints().iter().all(|ep| ep.preload().is_none()) {
+        return;
+    }
+
+    let mut ldap = match connect_and_bind(config.ldap(), metrics).await {
+        Ok(ldap) => ldap,
+        Err(e) => {
+            error!("Failed to connect to LDAP for cache preload, starting with a cold cache: {}", e);
+            return;
+        }
+    };
+
+    for endpoint in config.endpoints() {
+        let Some(preload) = endpoint.preload() else {
+            continue;
+        };
+
+        let names = preload_names(&mut ldap, metrics, endpoint, preload).await;
+        info!("Preloading {} names for {}", names.len(), endpoint.path());
+
+        let mut loaded = 0;
+        let mut errors = 0;
+        for name in names {
+            if cache.contains_key(&cache_key(endpoint.path(), &name)) {
+                continue;
+            }
+            match crate::refresh_cached_entry(&mut ldap, state, endpoint, &name).await {
+                Ok(_) => loaded += 1,
+                Err(e) => {
+                    warn!("Failed to preload {} for {}: {}", name, endpoint.path(), e);
+                    errors += 1;
+                }
+            }
+        }
+
+        info!("Preload of {} completed: {} loaded, {} errors", endpoint.path(), loaded, errors);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_read_names_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "# groups needed by the mail routers\nstaff\n\n  admins  \n#disabled").unwrap();
+
+        let names = read_names_file(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(names, vec!["staff", "admins"]);
+
+        assert!(read_names_file("/nonexistent/preload.txt").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use serde::Serialize;
+
+use crate::cache::{Format, unix_time};
+
+/// Header telling where the data of a response came from
+pub const X_CACHE: &str = "X-Cache";
+
+/// Values wrapped with where they came from, returned instead of the bare array on request
+#[derive(Debug, Serialize)]
+pub struct Envelope<'a> {
+    pub values: &'a [String],
+    pub count: usize,
+    /// Whether the values were served from the cache or a mirror rather than fetched for this request
+    pub cached: bool,
+    pub fetched_at: u64,
+    pub stale: bool,
+    pub endpoint: &'a str,
+    pub ldap_server: &'a str,
+}
+
+/// What clients need to cache a response and revalidate it later
+pub struct Validators {
+    /// Opaque tag of the response content, without quotes
+    pub etag: String,
+    /// The tag only covers the values, other parts of the body may differ between responses
+    pub weak: bool,
+    pub last_modified: SystemTime,
+    pub max_age: u64,
+}
+
+/// Check whether the client already has the current content, by `If-None-Match` or,
+/// when that is absent, by `If-Modified-Since`
+fn is_not_modified(headers: &HeaderMap, validators: &Validators) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        let Ok(if_none_match) = if_none_match.to_str() else {
+            return false;
+        };
+        return if_none_match.split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/").trim_matches('"') == validators.etag);
+    }
+
+    let if_modified_since = headers.get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    match if_modified_since {
+        // HTTP dates have a resolution of one second
+        Some(since) => UNIX_EPOCH + Duration::from_secs(unix_time(validators.last_modified)) <= since,
+        None => false,
+    }
+}
+
+/// Respond with a rendered body, or with 304 Not Modified if the request's conditions
+/// show the client already has it
+pub fn conditional_response(request_headers: &HeaderMap, content_type: &'static str, body: Bytes, validators: &Validators) -> Response {
+    let mut headers = HeaderMap::new();
+    let weak = if validators.weak { "W/" } else { "" };
+    if let Ok(etag) = HeaderValue::from_str(&format!("{}\"{}\"", weak, validators.etag)) {
+        headers.insert(header::ETAG, etag);
+    }
+    if let Ok(last_modified) = HeaderValue::from_str(&httpdate::fmt_http_date(validators.last_modified)) {
+        headers.insert(header::LAST_MODIFIED, last_modified);
+    }
+    if let Ok(cache_control) = HeaderValue::from_str(&format!("max-age={}", validators.max_age)) {
+        headers.insert(header::CACHE_CONTROL, cache_control);
+    }
+
+    headers.insert(header::VARY, HeaderValue::from_static("Accept"));
+
+    if is_not_modified(request_headers, validators) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    (headers, body).into_response()
+}
+
+/// Pick the format preferred by the request's `Accept` header among the ones we can
+/// produce, falling back to the endpoint's default format
+pub fn negotiate(headers: &HeaderMap, default: Format) -> Format {
+    let Some(accept) = headers.get(header::ACCEPT).and_then(|value| value.to_str().ok()) else {
+        return default;
+    };
+    // Comma-joined values are plain text too, so text/plain keeps them if they are the default
+    let text = if matches!(default, Format::Text | Format::Joined) { default } else { Format::Text };
+
+    let mut best: Option<(f32, Format)> = None;
+    for media_range in accept.split(',') {
+        let mut parts = media_range.split(';');
+        let media_type = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let quality = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality <= 0.0 {
+            continue;
+        }
+
+        let format = match media_type.as_str() {
+            "*/*" => default,
+            "application/json" | "application/*" => Format::Json,
+            "text/plain" => text,
+            "text/csv" => Format::Csv,
+            "text/*" if default == Format::Csv => Format::Csv,
+            "text/*" => text,
+            _ => continue,
+        };
+        if best.is_none_or(|(best_quality, _)| quality > best_quality) {
+            best = Some((quality, format));
+        }
+    }
+
+    best.map_or(default, |(_, format)| format)
+}
+
+/// Mark where a response came from
+pub fn with_cache_status(mut response: Response, status: &'static str) -> Response {
+    response.headers_mut().insert(X_CACHE, HeaderValue::from_static(status));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validators() -> Validators {
+        Validators {
+            etag: "0123abcd".to_string(),
+            weak: false,
+            last_modified: UNIX_EPOCH + Duration::from_millis(1_760_000_000_500),
+            max_age: 180,
+        }
+    }
+
+    fn request(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_conditional_response() {
+        let response = conditional_response(&HeaderMap::new(), "application/json", Bytes::from("[]"), &validators());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ETAG], "\"0123abcd\"");
+        assert_eq!(response.headers()[header::LAST_MODIFIED], "Thu, 09 Oct 2025 08:53:20 GMT");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "max-age=180");
+
+        for if_none_match in ["\"0123abcd\"", "W/\"0123abcd\"", "\"ffff\", \"0123abcd\"", "*"] {
+            let headers = request(header::IF_NONE_MATCH, if_none_match);
+            let response = conditional_response(&headers, "application/json", Bytes::from("[]"), &validators());
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{}", if_none_match);
+            assert_eq!(response.headers()[header::ETAG], "\"0123abcd\"");
+        }
+
+        let headers = request(header::IF_NONE_MATCH, "\"ffff\"");
+        assert!(!is_not_modified(&headers, &validators()));
+
+        // Weak tags are sent as such and compared like strong ones
+        let weak = Validators { weak: true, ..validators() };
+        let response = conditional_response(&HeaderMap::new(), "application/json", Bytes::from("[]"), &weak);
+        assert_eq!(response.headers()[header::ETAG], "W/\"0123abcd\"");
+        assert!(is_not_modified(&request(header::IF_NONE_MATCH, "W/\"0123abcd\""), &weak));
+
+        // If-None-Match takes precedence over If-Modified-Since
+        let mut headers = request(header::IF_MODIFIED_SINCE, "Thu, 09 Oct 2025 08:53:20 GMT");
+        assert!(is_not_modified(&headers, &validators()));
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"ffff\""));
+        assert!(!is_not_modified(&headers, &validators()));
+
+        let headers = request(header::IF_MODIFIED_SINCE, "Thu, 09 Oct 2025 08:53:19 GMT");
+        assert!(!is_not_modified(&headers, &validators()));
+        let headers = request(header::IF_MODIFIED_SINCE, "not a date");
+        assert!(!is_not_modified(&headers, &validators()));
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let accept = |value: &str| request(header::ACCEPT, value);
+
+        assert_eq!(negotiate(&HeaderMap::new(), Format::Csv), Format::Csv);
+        assert_eq!(negotiate(&accept("*/*"), Format::Joined), Format::Joined);
+        assert_eq!(negotiate(&accept("text/plain"), Format::Json), Format::Text);
+        assert_eq!(negotiate(&accept("text/plain"), Format::Joined), Format::Joined);
+        assert_eq!(negotiate(&accept("text/csv;q=0.5, application/json;q=0.9"), Format::Text), Format::Json);
+        assert_eq!(negotiate(&accept("application/json;q=0, text/*"), Format::Json), Format::Text);
+        // Unsupported types fall back to the default rather than failing the request
+        assert_eq!(negotiate(&accept("application/xml"), Format::Json), Format::Json);
+    }
+}
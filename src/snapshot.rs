@@ -0,0 +1,172 @@
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time::interval;
+
+use crate::{
+    cache::{Cache, CacheEntry, split_cache_key, unix_time},
+    config::{Config, SnapshotConfig, check_file_permissions},
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Snapshot {
+    written_at: u64,
+    entries: Vec<SnapshotEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct SnapshotEntry {
+    key: String,
+    values: Vec<String>,
+    dns: Vec<String>,
+    fetched_at: u64,
+}
+
+/// Write the whole cache to the snapshot file.
+///
+/// The snapshot is written to a temporary file with mode 600 and renamed into place,
+/// so readers never see a partial snapshot.
+pub fn write_snapshot(snapshot_config: &SnapshotConfig, cache: &Cache) -> Result<usize, Box<dyn std::error::Error>> {
+    let entries: Vec<SnapshotEntry> = cache.entries()
+        .into_iter()
+        .map(|(key, entry)| SnapshotEntry {
+            key,
+            values: entry.values.clone(),
+            dns: entry.dns.clone(),
+            fetched_at: unix_time(entry.fetched_at),
+        })
+        .collect();
+    let count = entries.len();
+
+    let snapshot = Snapshot {
+        written_at: unix_time(SystemTime::now()),
+        entries,
+    };
+
+    let path = snapshot_config.path();
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .map_err(|e| format!("Failed to create snapshot file {}: {}", tmp_path, e))?;
+    file.write_all(&serde_json::to_vec(&snapshot)?)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(count)
+}
+
+/// Load a previously written snapshot into the cache, marking every entry as stale
+/// until a refresh confirms it. Entries for endpoints that are no longer configured are skipped.
+pub fn load_snapshot(config: &Config, snapshot_config: &SnapshotConfig, cache: &Cache) -> Result<usize, Box<dyn std::error::Error>> {
+    let path = snapshot_config.path();
+    if !Path::new(path).exists() {
+        info!("No cache snapshot found at {}, starting with an empty cache", path);
+        return Ok(0);
+    }
+
+    // The snapshot holds directory data, so it must be as well protected as the config
+    check_file_permissions(path, "Snapshot file")?;
+
+    let content = fs::read(path)
+        .map_err(|e| format!("Failed to read snapshot file: {e}"))?;
+    let snapshot: Snapshot = serde_json::from_slice(&content)
+        .map_err(|e| format!("Failed to parse snapshot file: {e}"))?;
+
+    let mut loaded = 0;
+    for snapshot_entry in snapshot.entries {
+        let configured = split_cache_key(&snapshot_entry.key)
+            .is_some_and(|(endpoint_path, _)| config.endpoints().iter().any(|ep| ep.path() == endpoint_path));
+        if !configured {
+            warn!("Skipping snapshot entry '{}' for an endpoint that is no longer configured", snapshot_entry.key);
+            continue;
+        }
+
+        let mut entry = CacheEntry::new(snapshot_entry.values, snapshot_entry.dns);
+        entry.fetched_at = UNIX_EPOCH + Duration::from_secs(snapshot_entry.fetched_at);
+        entry.modified_at = entry.fetched_at;
+        entry.stale = true;
+        cache.insert(snapshot_entry.key, entry);
+        loaded += 1;
+    }
+
+    info!("Loaded {} cached entries from snapshot written at {}", loaded, snapshot.written_at);
+    Ok(loaded)
+}
+
+/// Periodically write the cache to the snapshot file
+pub async fn run_snapshot_writer(snapshot_config: SnapshotConfig, cache: Cache) {
+    let mut interval = interval(Duration::from_secs(*snapshot_config.interval_secs()));
+    // The first tick completes immediately, there is nothing worth writing yet
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        match write_snapshot(&snapshot_config, &cache) {
+            Ok(count) => info!("Wrote {} cached entries to snapshot {}", count, snapshot_config.path()),
+            Err(e) => error!("Failed to write cache snapshot: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::cache_key;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("cache.snapshot");
+        let snapshot_config: SnapshotConfig = serde_yaml::from_str(&format!("path: {}", path.display())).unwrap();
+        let config: Config = serde_yaml::from_str(r#"
+ldap:
+  url: "ldap://localhost:389"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={})"
+    search_scope: "subtree"
+    attribute: "member"
+"#).unwrap();
+
+        let cache = Cache::new();
+        cache.insert(cache_key("/group_members", "staff"),
+            CacheEntry::new(vec!["alice".to_string()], vec!["cn=staff,ou=groups,dc=example,dc=com".to_string()]));
+        cache.insert(cache_key("/removed", "staff"), CacheEntry::new(vec![], vec![]));
+
+        assert_eq!(write_snapshot(&snapshot_config, &cache).unwrap(), 2);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o077, 0);
+
+        let restored = Cache::new();
+        // The snapshot is not owned by root when tests run unprivileged
+        if fs::metadata(&path).unwrap().uid() != 0 {
+            assert!(load_snapshot(&config, &snapshot_config, &restored).is_err());
+            return;
+        }
+        assert_eq!(load_snapshot(&config, &snapshot_config, &restored).unwrap(), 1);
+
+        let entry = restored.get(&cache_key("/group_members", "staff")).unwrap();
+        assert!(entry.stale);
+        assert_eq!(entry.values, vec!["alice"]);
+        assert_eq!(entry.dns, vec!["cn=staff,ou=groups,dc=example,dc=com"]);
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use ldap3::{
+    Scope, SearchEntry,
+    controls::{ControlType, EntryState, MakeCritical, RefreshMode, SyncInfo, SyncRequest, SyncState, parse_syncinfo},
+};
+use log::{debug, error, info, warn};
+use tokio::time::{Duration, sleep};
+
+use crate::{
+    AppState,
+    cache::{Cache, dn_is_under, normalize_dn, split_cache_key},
+    config::{ChangeTrackingConfig, Config},
+    ldap::connect_and_bind,
+};
+
+/// Set while a change tracking session is keeping the cache up to date.
+/// The polling refresh loop skips its cycles while this is set.
+pub type SyncActive = Arc<AtomicBool>;
+
+/// Find the cache keys whose data may be affected by a change to the entry at `dn`.
+///
+/// An entry is affected if it was built from `dn`. Entries that found nothing at all are
+/// also refreshed when the change happens below their endpoint's search base, since the
+/// changed entry may be the one they were looking for.
+pub fn affected_keys(config: &Config, cache: &Cache, dn: &str) -> Vec<String> {
+    let dn = normalize_dn(dn);
+
+    cache.entries()
+        .into_iter()
+        .filter(|(cache_key, entry)| {
+            if entry.dns.contains(&dn) {
+                return true;
+            }
+            if !entry.dns.is_empty() {
+                return false;
+            }
+            let Some((endpoint_path, _)) = split_cache_key(cache_key) else {
+                return false;
+            };
+            config.endpoints()
+                .iter()
+                .find(|ep| ep.path() == endpoint_path)
+                .is_some_and(|ep| dn_is_under(&dn, &normalize_dn(ep.search_base())))
+        })
+        .map(|(cache_key, _)| cache_key)
+        .collect()
+}
+
+/// Refresh every cached entry affected by a change to `dn`, dropping entries that can no
+/// longer be refreshed so they are fetched again on the next request
+pub async fn apply_change(ldap: &mut ldap3::Ldap, state: &AppState, dn: &str) {
+    let AppState { config, cache, .. } = state;
+    let keys = affected_keys(config, cache, dn);
+
+    if keys.is_empty() {
+        debug!("Change to '{}' does not affect any cached entries", dn);
+        return;
+    }
+
+    info!("Change to '{}' affects {} cached entries", dn, keys.len());
+
+    for cache_key in keys {
+        let Some((endpoint_path, name)) = split_cache_key(&cache_key) else {
+            continue;
+        };
+        let Some(endpoint) = config.endpoints().iter().find(|ep| ep.path() == endpoint_path) else {
+            continue;
+        };
+
+        let failed = match crate::refresh_cached_entry(ldap, state, endpoint, name).await {
+            Ok(_) => false,
+            Err(e) => {
+                error!("Failed to refresh cache for {} after change notification: {}", cache_key, e);
+                true
+            }
+        };
+        if failed && cache.remove(&cache_key).is_some() {
+            state.metrics.cache_evictions.with_label_values(&["change_tracking"]).inc();
+        }
+    }
+}
+
+/// Keep the cache up to date with an RFC 4533 refreshAndPersist session.
+///
+/// Whenever the session cannot be established or drops, `sync_active` is cleared so the
+/// polling refresh loop takes over, and the session is retried after `retry_interval_secs`.
+pub async fn run_syncrepl(state: Arc<AppState>, sync_active: SyncActive) {
+    let Some(tracking) = state.config.ldap().change_tracking().clone() else {
+        return;
+    };
+    let retry_interval = Duration::from_secs(*tracking.retry_interval_secs());
+
+    loop {
+        match syncrepl_session(&state, &tracking, &sync_active).await {
+            Ok(_) => warn!("Syncrepl session ended, falling back to polling"),
+            Err(e) => error!("Syncrepl session failed, falling back to polling: {}", e),
+        }
+        sync_active.store(false, Ordering::SeqCst);
+
+        sleep(retry_interval).await;
+    }
+}
+
+async fn syncrepl_session(
+    state: &Arc<AppState>,
+    tracking: &ChangeTrackingConfig,
+    sync_active: &SyncActive,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut ldap = connect_and_bind(state.config.ldap(), &state.metrics).await?;
+    // Refreshes share the connection with the persistent search
+    let mut refresher = ldap.clone();
+
+    info!("Starting syncrepl session on '{}'", tracking.search_base());
+
+    let request = SyncRequest {
+        mode: RefreshMode::RefreshAndPersist,
+        cookie: None,
+        reload_hint: false,
+    };
+    let mut stream = ldap
+        .with_controls(request.critical())
+        .streaming_search(tracking.search_base(), Scope::Subtree, tracking.search_filter(), vec!["1.1"])
+        .await?;
+
+    // Without a cookie the refresh phase sends every entry in the subtree, which we do not need
+    let mut persisting = false;
+
+    while let Some(entry) = stream.next().await? {
+        if entry.is_intermediate() {
+            match parse_syncinfo(entry) {
+                SyncInfo::RefreshDelete { refresh_done: true, .. }
+                | SyncInfo::RefreshPresent { refresh_done: true, .. } if !persisting => {
+                    persisting = true;
+                    sync_active.store(true, Ordering::SeqCst);
+                    info!("Syncrepl session established, polling suspended");
+
+                    // Pick up anything that changed since the last poll
+                    crate::refresh_cache(state.clone()).await;
+                }
+                other => debug!("Syncrepl info message: {:?}", other),
+            }
+            continue;
+        }
+
+        if !persisting {
+            continue;
+        }
+
+        let sync_state = entry.1.iter().find_map(|ctrl| match ctrl.0 {
+            Some(ControlType::SyncState) => Some(ctrl.1.parse::<SyncState>()),
+            _ => None,
+        });
+        let entry = SearchEntry::construct(entry);
+
+        match sync_state {
+            Some(SyncState { state: EntryState::Present, .. }) => (),
+            Some(sync_state) => {
+                debug!("Syncrepl {:?} for '{}'", sync_state.state, entry.dn);
+                apply_change(&mut refresher, state, &entry.dn).await;
+            }
+            None => warn!("Syncrepl entry '{}' without sync state control", entry.dn),
+        }
+    }
+
+    stream.finish().await.success()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::CacheEntry, events::EventLog};
+    use bytes::BytesMut;
+    use ldap3::asn1::{ASNTag, Enumerated, Integer, PL, StructureTag, Tag, TagClass, parse_tag, parse_uint, write};
+    use std::{collections::HashMap, sync::Mutex};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    const SYNC_REQUEST_OID: &str = "1.3.6.1.4.1.4203.1.9.1.1";
+    const SYNC_STATE_OID: &str = "1.3.6.1.4.1.4203.1.9.1.2";
+    const SYNC_INFO_OID: &str = "1.3.6.1.4.1.4203.1.9.1.4";
+
+    fn test_config() -> Config {
+        serde_yaml::from_str(r#"
+ldap:
+  url: "ldap://localhost:389"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={})"
+    search_scope: "subtree"
+    attribute: "member"
+"#).unwrap()
+    }
+
+    #[test]
+    fn test_affected_keys() {
+        let config = test_config();
+        let cache = Cache::new();
+        cache.insert(
+            "/group_members:staff".to_string(),
+            CacheEntry::new(vec!["uid=alice,ou=users,dc=example,dc=com".to_string()],
+                vec!["cn=staff,ou=groups,dc=example,dc=com".to_string()]),
+        );
+        cache.insert("/group_members:missing".to_string(), CacheEntry::new(vec![], vec![]));
+
+        // DN comparison ignores case and spacing
+        let mut keys = affected_keys(&config, &cache, "CN=staff, ou=Groups,dc=example,dc=com");
+        keys.sort();
+        assert_eq!(keys, vec!["/group_members:missing", "/group_members:staff"]);
+
+        // Changes outside the search base only affect entries built from them
+        let keys = affected_keys(&config, &cache, "uid=alice,ou=users,dc=example,dc=com");
+        assert!(keys.is_empty());
+    }
+
+    fn encode(tag: StructureTag) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        write::encode_into(&mut buf, tag).unwrap();
+        buf.to_vec()
+    }
+
+    fn constructed(class: TagClass, id: u64, inner: Vec<StructureTag>) -> StructureTag {
+        StructureTag { class, id, payload: PL::C(inner) }
+    }
+
+    fn octets(value: impl Into<Vec<u8>>) -> StructureTag {
+        StructureTag { class: TagClass::Universal, id: 4, payload: PL::P(value.into()) }
+    }
+
+    fn enumerated(value: i64) -> StructureTag {
+        Tag::Enumerated(Enumerated { inner: value, ..Default::default() }).into_structure()
+    }
+
+    fn success(id: u64) -> StructureTag {
+        constructed(TagClass::Application, id, vec![enumerated(0), octets(""), octets("")])
+    }
+
+    fn search_entry(dn: &str, attribute: &str, values: &[String]) -> StructureTag {
+        let values = values.iter().map(|value| octets(value.as_str())).collect();
+        let attribute = constructed(TagClass::Universal, 16, vec![octets(attribute), constructed(TagClass::Universal, 17, values)]);
+        constructed(TagClass::Application, 4, vec![octets(dn), constructed(TagClass::Universal, 16, vec![attribute])])
+    }
+
+    fn sync_state(state: i64) -> StructureTag {
+        let value = constructed(TagClass::Universal, 16, vec![enumerated(state), octets([0u8; 16])]);
+        constructed(TagClass::Universal, 16, vec![octets(SYNC_STATE_OID), octets(encode(value))])
+    }
+
+    fn refresh_done() -> StructureTag {
+        let value = constructed(TagClass::Context, 1, vec![]);
+        constructed(TagClass::Application, 25, vec![
+            StructureTag { class: TagClass::Context, id: 0, payload: PL::P(SYNC_INFO_OID.into()) },
+            StructureTag { class: TagClass::Context, id: 1, payload: PL::P(encode(value)) },
+        ])
+    }
+
+    async fn send(stream: &mut TcpStream, message_id: i64, op: StructureTag, controls: Vec<StructureTag>) {
+        let mut message = vec![Tag::Integer(Integer { inner: message_id, ..Default::default() }).into_structure(), op];
+        if !controls.is_empty() {
+            message.push(constructed(TagClass::Context, 0, controls));
+        }
+        stream.write_all(&encode(constructed(TagClass::Universal, 16, message))).await.unwrap();
+    }
+
+    /// Searches seen by the stand-in server as (sent on the syncrepl connection, filter value)
+    type SearchLog = Arc<Mutex<Vec<(bool, String)>>>;
+
+    /// Serve one connection of a stand-in directory holding the `member` values of groups by `cn`.
+    ///
+    /// A search with the sync request control gets an Add during the refresh phase, the end of
+    /// the refresh phase, a Present and a Modify of `cn=staff`. It ends once a search for the
+    /// modified group has been answered on the same connection.
+    async fn serve_connection(mut stream: TcpStream, groups: Arc<HashMap<String, Vec<String>>>, searches: SearchLog) {
+        let mut buf = Vec::new();
+        let mut sync_search = None;
+
+        loop {
+            let (message, consumed) = match parse_tag(&buf) {
+                Ok((rest, message)) => (message, buf.len() - rest.len()),
+                Err(e) if e.is_incomplete() => {
+                    let mut chunk = [0u8; 4096];
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                    continue;
+                }
+                Err(e) => panic!("Invalid LDAP message: {:?}", e),
+            };
+            buf.drain(..consumed);
+
+            let mut parts = message.expect_constructed().unwrap().into_iter();
+            let message_id = parse_uint(&parts.next().unwrap().expect_primitive().unwrap()).unwrap().1 as i64;
+            let op = parts.next().unwrap();
+            let is_sync_search = parts.next().is_some_and(|controls| {
+                controls.expect_constructed().unwrap().into_iter().any(|control| {
+                    let oid = control.expect_constructed().unwrap().remove(0).expect_primitive().unwrap();
+                    oid == SYNC_REQUEST_OID.as_bytes()
+                })
+            });
+
+            match op.id {
+                // Bind
+                0 => send(&mut stream, message_id, success(1), vec![]).await,
+                // Unbind
+                2 => return,
+                // Search
+                3 if is_sync_search => {
+                    sync_search = Some(message_id);
+                    let other = "cn=other,ou=groups,dc=example,dc=com";
+                    send(&mut stream, message_id, search_entry(other, "objectClass", &[]), vec![sync_state(1)]).await;
+                    send(&mut stream, message_id, refresh_done(), vec![]).await;
+                    send(&mut stream, message_id, search_entry(other, "objectClass", &[]), vec![sync_state(0)]).await;
+                    let staff = "cn=staff,ou=groups,dc=example,dc=com";
+                    send(&mut stream, message_id, search_entry(staff, "objectClass", &[]), vec![sync_state(2)]).await;
+                }
+                3 => {
+                    // The filter is an equality match on cn
+                    let filter = op.expect_constructed().unwrap().remove(6);
+                    let name = String::from_utf8(filter.expect_constructed().unwrap().remove(1).expect_primitive().unwrap()).unwrap();
+                    searches.lock().unwrap().push((sync_search.is_some(), name.clone()));
+
+                    let dn = format!("cn={},ou=groups,dc=example,dc=com", name);
+                    send(&mut stream, message_id, search_entry(&dn, "member", &groups[&name]), vec![]).await;
+                    send(&mut stream, message_id, success(5), vec![]).await;
+                    if let Some(sync_search) = sync_search.take() {
+                        send(&mut stream, sync_search, success(5), vec![]).await;
+                    }
+                }
+                other => panic!("Unexpected LDAP operation {}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_syncrepl_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let groups = Arc::new(HashMap::from([
+            ("staff".to_string(), vec!["uid=bob,ou=users,dc=example,dc=com".to_string(), "uid=alice,ou=users,dc=example,dc=com".to_string()]),
+            ("other".to_string(), vec!["uid=carol,ou=users,dc=example,dc=com".to_string()]),
+        ]));
+        let searches = SearchLog::default();
+        {
+            let searches = searches.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve_connection(stream, groups.clone(), searches.clone()));
+                }
+            });
+        }
+
+        let config: Config = serde_yaml::from_str(&format!(r#"
+ldap:
+  url: "ldap://{}"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+  change_tracking:
+    type: "syncrepl"
+    search_base: "dc=example,dc=com"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={{}})"
+    search_scope: "subtree"
+    attribute: "member"
+"#, addr)).unwrap();
+        let tracking = config.ldap().change_tracking().clone().unwrap();
+        let state = Arc::new(AppState::new(Arc::new(config), Cache::new(), Arc::new(EventLog::new(10, None))));
+        for name in ["staff", "other"] {
+            state.cache.insert(
+                format!("/group_members:{}", name),
+                CacheEntry::new(vec![], vec![format!("cn={},ou=groups,dc=example,dc=com", name)]),
+            );
+        }
+
+        let sync_active = SyncActive::default();
+        tokio::time::timeout(Duration::from_secs(10), syncrepl_session(&state, &tracking, &sync_active))
+            .await
+            .expect("syncrepl session did not finish")
+            .unwrap();
+
+        // The end of the refresh phase suspends polling and refreshes every cached entry
+        assert!(sync_active.load(Ordering::SeqCst));
+        let mut searches = searches.lock().unwrap().clone();
+        searches.sort();
+        assert_eq!(searches, vec![
+            (false, "other".to_string()),
+            (false, "staff".to_string()),
+            // Only the Modify is applied, on the syncrepl connection
+            (true, "staff".to_string()),
+        ]);
+
+        let staff = state.cache.get("/group_members:staff").unwrap();
+        assert_eq!(staff.values, vec!["uid=alice,ou=users,dc=example,dc=com", "uid=bob,ou=users,dc=example,dc=com"]);
+        assert!(staff.last_refresh.as_ref().is_some_and(|outcome| outcome.error.is_none()));
+    }
+}
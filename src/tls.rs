@@ -0,0 +1,286 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    sync::{Arc, Mutex, RwLock},
+    time::SystemTime,
+};
+
+use log::{error, info};
+use tokio::time::{Duration, interval};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{self, RootCertStore, ServerConfig, pki_types::CertificateDer, server::WebPkiClientVerifier},
+};
+
+use crate::config::{TlsConfig, check_file_permissions};
+
+/// Read every certificate of a PEM file
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, Box<dyn std::error::Error>> {
+    let file = File::open(path).map_err(|e| format!("Failed to open certificate file {}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to parse certificate file {}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path).into());
+    }
+    Ok(certs)
+}
+
+/// Build the rustls configuration from the certificate, key and client CA files
+fn build_server_config(tls: &TlsConfig) -> Result<Arc<ServerConfig>, Box<dyn std::error::Error>> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let certs = read_certs(tls.cert_file())?;
+    let key_file = File::open(tls.key_file()).map_err(|e| format!("Failed to open key file {}: {}", tls.key_file(), e))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+        .map_err(|e| format!("Failed to parse key file {}: {}", tls.key_file(), e))?
+        .ok_or_else(|| format!("No private key found in {}", tls.key_file()))?;
+
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+    let builder = match tls.client_ca_file() {
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca_file)? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match tls.client_auth().as_deref() {
+                Some("optional") => verifier.allow_unauthenticated().build()?,
+                _ => verifier.build()?,
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_single_cert(certs, key)?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(server_config))
+}
+
+/// Check the key file and build the rustls configuration
+fn load_server_config(tls: &TlsConfig) -> Result<Arc<ServerConfig>, Box<dyn std::error::Error>> {
+    check_file_permissions(tls.key_file(), "TLS key file")?;
+    build_server_config(tls)
+}
+
+/// Modification times of the files a configuration was built from
+fn modified_times(tls: &TlsConfig) -> Vec<Option<SystemTime>> {
+    [Some(tls.cert_file()), Some(tls.key_file()), tls.client_ca_file().as_ref()]
+        .into_iter()
+        .flatten()
+        .map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+/// Loads the rustls configuration from the files of a TLS configuration
+type LoadServerConfig = fn(&TlsConfig) -> Result<Arc<ServerConfig>, Box<dyn std::error::Error>>;
+
+/// The current TLS configuration, replaced whenever its files change on disk.
+/// Established connections keep the configuration they were accepted with.
+pub struct TlsState {
+    tls: TlsConfig,
+    load: LoadServerConfig,
+    current: RwLock<Arc<ServerConfig>>,
+    modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl TlsState {
+    pub fn new(tls: TlsConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let modified = modified_times(&tls);
+        let server_config = load_server_config(&tls)?;
+        Ok(TlsState { tls, load: load_server_config, current: RwLock::new(server_config), modified: Mutex::new(modified) })
+    }
+
+    /// Acceptor for the next connection, with the current certificate
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+
+    /// Reload the configuration if any of its files changed, keeping the current one if
+    /// the new files cannot be loaded
+    fn reload_if_changed(&self) {
+        let modified = modified_times(&self.tls);
+        let mut last_modified = self.modified.lock().unwrap();
+        if *last_modified == modified {
+            return;
+        }
+        *last_modified = modified;
+
+        match (self.load)(&self.tls) {
+            Ok(server_config) => {
+                *self.current.write().unwrap() = server_config;
+                info!("Reloaded TLS certificate {}", self.tls.cert_file());
+            }
+            Err(e) => error!("Failed to reload TLS certificate, keeping the current one: {}", e),
+        }
+    }
+}
+
+/// Watch the certificate, key and client CA files and reload them when they change
+pub async fn run_tls_reload(state: Arc<TlsState>) {
+    let mut interval = interval(Duration::from_secs(*state.tls.reload_interval_secs()));
+    // The first tick completes immediately, the files were just loaded
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        state.reload_if_changed();
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, IsCa, KeyPair};
+    use tokio_rustls::{TlsConnector, rustls::{ClientConfig, pki_types::{PrivateKeyDer, ServerName}}};
+
+    /// A CA with a server certificate for localhost and a client certificate, all in PEM
+    pub struct TestPki {
+        pub ca: String,
+        pub server_cert: String,
+        pub server_key: String,
+        pub client_cert: String,
+        pub client_key: String,
+    }
+
+    pub fn test_pki() -> TestPki {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let issue = |names: Vec<String>, common_name: &str| {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(names).unwrap();
+            params.distinguished_name.push(rcgen::DnType::CommonName, common_name);
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            CertifiedKey { cert, key_pair: key }
+        };
+        let server = issue(vec!["localhost".to_string()], "localhost");
+        let client = issue(vec![], "mail.example.com");
+
+        TestPki {
+            ca: ca.pem(),
+            server_cert: server.cert.pem(),
+            server_key: server.key_pair.serialize_pem(),
+            client_cert: client.cert.pem(),
+            client_key: client.key_pair.serialize_pem(),
+        }
+    }
+
+    /// Write the server side of a test PKI to `dir` and return its TLS configuration
+    pub fn write_tls_config(dir: &std::path::Path, pki: &TestPki, client_auth: Option<&str>) -> TlsConfig {
+        fs::write(dir.join("server.pem"), &pki.server_cert).unwrap();
+        fs::write(dir.join("server.key"), &pki.server_key).unwrap();
+        fs::write(dir.join("ca.pem"), &pki.ca).unwrap();
+        let mut yaml = format!("cert_file: \"{}\"\nkey_file: \"{}\"\n",
+            dir.join("server.pem").display(), dir.join("server.key").display());
+        if let Some(client_auth) = client_auth {
+            yaml.push_str(&format!("client_ca_file: \"{}\"\nclient_auth: \"{}\"\n", dir.join("ca.pem").display(), client_auth));
+        }
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    /// State serving a configuration, without the key file checks that need root
+    pub fn tls_state(tls: TlsConfig) -> TlsState {
+        TlsState {
+            load: build_server_config,
+            current: RwLock::new(build_server_config(&tls).unwrap()),
+            modified: Mutex::new(modified_times(&tls)),
+            tls,
+        }
+    }
+
+    /// Client configuration trusting the test CA, presenting the client certificate if asked
+    pub fn client_config(pki: &TestPki, with_client_cert: bool) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut pki.ca.as_bytes()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let builder = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = if with_client_cert {
+            let certs = rustls_pemfile::certs(&mut pki.client_cert.as_bytes()).collect::<Result<Vec<_>, _>>().unwrap();
+            let key: PrivateKeyDer = rustls_pemfile::private_key(&mut pki.client_key.as_bytes()).unwrap().unwrap();
+            builder.with_client_auth_cert(certs, key).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        Arc::new(config)
+    }
+
+    /// Handshake with a TLS server on `addr` and send one HTTP/1.1 GET request
+    pub async fn https_get(addr: std::net::SocketAddr, client: Arc<ClientConfig>, path: &str) -> std::io::Result<String> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        let mut stream = TlsConnector::from(client).connect(ServerName::try_from("localhost").unwrap(), stream).await?;
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    #[test]
+    fn test_build_server_config() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let pki = test_pki();
+
+        let server_config = build_server_config(&write_tls_config(dir.path(), &pki, None)).unwrap();
+        assert_eq!(server_config.alpn_protocols[1], b"http/1.1");
+        assert!(build_server_config(&write_tls_config(dir.path(), &pki, Some("required"))).is_ok());
+
+        // A key that is not PEM fails to load
+        fs::write(dir.path().join("server.key"), "not a key").unwrap();
+        let tls: TlsConfig = serde_yaml::from_str(&format!("cert_file: \"{}\"\nkey_file: \"{}\"",
+            dir.path().join("server.pem").display(), dir.path().join("server.key").display())).unwrap();
+        assert!(build_server_config(&tls).is_err());
+    }
+
+    /// Handshake with `acceptor` and return the certificate the server presented
+    async fn presented_certificate(acceptor: TlsAcceptor, client: Arc<ClientConfig>) -> CertificateDer<'static> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            acceptor.accept(stream).await.unwrap()
+        });
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let stream = TlsConnector::from(client).connect(ServerName::try_from("localhost").unwrap(), stream).await.unwrap();
+        server.await.unwrap();
+        stream.get_ref().1.peer_certificates().unwrap()[0].clone().into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_reload_if_changed() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let pki = test_pki();
+        let state = tls_state(write_tls_config(dir.path(), &pki, None));
+        let pem = |cert: &str| rustls_pemfile::certs(&mut cert.as_bytes()).next().unwrap().unwrap();
+
+        assert_eq!(presented_certificate(state.acceptor(), client_config(&pki, false)).await, pem(&pki.server_cert));
+
+        // Nothing changed, nothing is reloaded
+        let current = state.acceptor();
+        state.reload_if_changed();
+        assert!(Arc::ptr_eq(&current.config().clone(), &state.acceptor().config().clone()));
+
+        // A renewed certificate is presented to new connections
+        let renewed = test_pki();
+        fs::write(dir.path().join("server.pem"), &renewed.server_cert).unwrap();
+        fs::write(dir.path().join("server.key"), &renewed.server_key).unwrap();
+        // Make sure the change shows even where timestamps are coarse
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options().write(true).open(dir.path().join("server.pem")).unwrap().set_modified(later).unwrap();
+        state.reload_if_changed();
+        assert_eq!(presented_certificate(state.acceptor(), client_config(&renewed, false)).await, pem(&renewed.server_cert));
+
+        // Broken files are not loaded, the renewed certificate stays
+        fs::write(dir.path().join("server.key"), "not a key").unwrap();
+        File::options().write(true).open(dir.path().join("server.key")).unwrap().set_modified(later).unwrap();
+        state.reload_if_changed();
+        assert_eq!(presented_certificate(state.acceptor(), client_config(&renewed, false)).await, pem(&renewed.server_cert));
+    }
+}
@@ -0,0 +1,173 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    Extension,
+    extract::{Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Response, sse::{Event, KeepAlive, Sse}},
+};
+use futures::{Stream, stream};
+use log::{info, warn};
+use tokio::{
+    sync::broadcast::{Receiver, error::RecvError},
+    time::Duration,
+};
+
+use crate::{
+    AppState,
+    cache::{Cache, CacheChange, CacheEntry, cache_key},
+    config::EndpointConfig,
+    handler::{LookupName, fetch_and_cache},
+};
+
+/// Interval of the comments sent to keep idle watch connections open
+const HEARTBEAT_SECS: u64 = 15;
+
+/// Build the SSE event carrying the values of an entry, identified by its version
+fn update_event(entry: &CacheEntry) -> Event {
+    let json = entry.json();
+    Event::default()
+        .event("update")
+        .id(entry.version.to_string())
+        .data(std::str::from_utf8(&json).unwrap())
+}
+
+struct WatchState {
+    cache: Cache,
+    cache_key: String,
+    changes: Receiver<Arc<CacheChange>>,
+    /// Current entry, sent before waiting for changes if it is newer than what the client has seen
+    pending: Option<Arc<CacheEntry>>,
+    last_version: u64,
+}
+
+/// Stream an update event whenever the values of the watched entry change
+fn updates(state: WatchState) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(entry) = state.pending.take()
+                && entry.version > state.last_version
+            {
+                state.last_version = entry.version;
+                return Some((Ok(update_event(&entry)), state));
+            }
+
+            match state.changes.recv().await {
+                Ok(change) if change.key == state.cache_key => state.pending = Some(change.entry.clone()),
+                Ok(_) => (),
+                Err(RecvError::Lagged(missed)) => {
+                    // The change may have been among the missed ones, catch up from the cache
+                    warn!("Watch of '{}' missed {} changes", state.cache_key, missed);
+                    state.pending = state.cache.get(&state.cache_key);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+/// Stream the values of one name as Server-Sent Events: the current values first,
+/// then the new values whenever a refresh changes them.
+///
+/// Every event carries the version of the entry as its id. A client reconnecting with
+/// `Last-Event-ID` only receives the current values if they changed since that version.
+pub async fn watch_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(endpoint): Extension<Arc<EndpointConfig>>,
+    LookupName(name): LookupName,
+    request: Request,
+) -> Response {
+    let AppState { cache, limits, .. } = &*state;
+    let endpoint: &EndpointConfig = &endpoint;
+
+    let last_version = request.headers().get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0);
+
+    info!("Watching '{}' on endpoint '{}' from version {}", name, endpoint.path(), last_version);
+
+    // Subscribe before reading the entry so no change can slip in between
+    let changes = cache.subscribe();
+    let cache_key = cache_key(endpoint.path(), &name);
+    let entry = match cache.get(&cache_key) {
+        Some(entry) => entry,
+        None => {
+            let _permit = match limits.acquire_miss().await {
+                Ok(permit) => permit,
+                Err(busy) => return busy.into_response(),
+            };
+            match fetch_and_cache(&state, endpoint, &name).await {
+                Ok(entry) => entry,
+                Err(e) => return (StatusCode::BAD_GATEWAY, e).into_response(),
+            }
+        }
+    };
+
+    let state = WatchState {
+        cache: cache.clone(),
+        cache_key,
+        changes,
+        pending: Some(entry),
+        last_version,
+    };
+
+    Sse::new(updates(state))
+        .keep_alive(KeepAlive::new().interval(Duration::from_secs(HEARTBEAT_SECS)).text("heartbeat"))
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn watch(cache: &Cache, last_version: u64) -> impl Stream<Item = Result<Event, Infallible>> {
+        let cache_key = cache_key("/group_members", "staff");
+        updates(WatchState {
+            cache: cache.clone(),
+            changes: cache.subscribe(),
+            pending: cache.get(&cache_key),
+            cache_key,
+            last_version,
+        })
+    }
+
+    fn staff(members: &[&str]) -> CacheEntry {
+        CacheEntry::new(members.iter().map(|member| member.to_string()).collect(), vec![])
+    }
+
+    async fn next_event(updates: &mut (impl Stream<Item = Result<Event, Infallible>> + Unpin)) -> String {
+        let event = tokio::time::timeout(Duration::from_secs(1), updates.next()).await.unwrap().unwrap().unwrap();
+        format!("{:?}", event)
+    }
+
+    #[tokio::test]
+    async fn test_updates() {
+        let cache = Cache::new();
+        let key = cache_key("/group_members", "staff");
+        let first = cache.insert(key.clone(), staff(&["alice"]));
+
+        let mut updates = Box::pin(watch(&cache, 0));
+        assert!(next_event(&mut updates).await.contains(&first.version.to_string()));
+
+        // Unchanged refreshes and other keys do not produce events
+        cache.insert(key.clone(), staff(&["alice"]));
+        cache.insert(cache_key("/group_members", "admins"), staff(&["root"]));
+        cache.insert(cache_key("/group_members", "admins"), staff(&["carol"]));
+        let second = cache.insert(key.clone(), staff(&["alice", "bob"]));
+        let event = next_event(&mut updates).await;
+        assert!(event.contains(&second.version.to_string()));
+        assert!(event.contains("bob"));
+
+        // Resuming from the current version waits for the next change
+        let mut resumed = Box::pin(watch(&cache, second.version));
+        let third = cache.insert(key.clone(), staff(&["bob"]));
+        assert!(next_event(&mut resumed).await.contains(&third.version.to_string()));
+
+        // An entry fetched again after it was invalidated reaches watchers as well
+        cache.remove(&key);
+        let fourth = cache.insert(key.clone(), staff(&["bob"]));
+        assert!(next_event(&mut resumed).await.contains(&fourth.version.to_string()));
+    }
+}
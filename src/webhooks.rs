@@ -0,0 +1,356 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::{
+    sync::{
+        broadcast::{Receiver, error::RecvError},
+        mpsc,
+    },
+    time::{Duration, sleep},
+};
+
+use crate::{
+    cache::unix_time,
+    config::{Config, WebhookConfig},
+    events::ChangeEvent,
+};
+
+/// Header carrying the hex encoded HMAC-SHA256 of the request body
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+/// Header carrying the id of the change event, identical across retries
+pub const EVENT_ID_HEADER: &str = "X-Event-Id";
+
+/// Backoff between attempts never grows beyond this
+const MAX_RETRY_BACKOFF_SECS: u64 = 300;
+
+/// A delivery that failed every attempt, as written to the dead letter log
+#[derive(Debug, Serialize)]
+struct DeadLetter<'a> {
+    at: u64,
+    url: &'a str,
+    attempts: u32,
+    error: &'a str,
+    event: &'a ChangeEvent,
+}
+
+/// Sign a request body, formatted as `sha256=<hex>`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Post one event to a webhook, succeeding only on a 2xx response
+async fn deliver(client: &reqwest::Client, webhook: &WebhookConfig, event: &ChangeEvent, body: &[u8]) -> Result<(), String> {
+    let response = client.post(webhook.url())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign(webhook.secret(), body))
+        .header(EVENT_ID_HEADER, event.id)
+        .body(body.to_vec())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !response.status().is_success() {
+        return Err(format!("webhook responded with {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Deliver the events of one webhook in order, retrying each with exponential backoff
+/// and writing it to the dead letter log once every attempt has failed
+async fn run_webhook(
+    webhook: WebhookConfig,
+    mut queue: mpsc::UnboundedReceiver<ChangeEvent>,
+    dead_letters: Option<Arc<Mutex<File>>>,
+) {
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(*webhook.timeout_secs())).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create HTTP client for webhook {}: {}", webhook.url(), e);
+            return;
+        }
+    };
+
+    while let Some(event) = queue.recv().await {
+        let body = serde_json::to_vec(&event).unwrap();
+        let mut backoff = *webhook.retry_backoff_secs();
+        let mut attempt = 1;
+
+        loop {
+            let error = match deliver(&client, &webhook, &event, &body).await {
+                Ok(_) => {
+                    info!("Delivered change event {} to webhook {}", event.id, webhook.url());
+                    break;
+                }
+                Err(e) => e,
+            };
+
+            if attempt < *webhook.max_attempts() {
+                warn!("Failed to deliver change event {} to webhook {} (attempt {}), retrying in {}s: {}",
+                    event.id, webhook.url(), attempt, backoff, error);
+                sleep(Duration::from_secs(backoff)).await;
+                backoff = (backoff * 2).min(MAX_RETRY_BACKOFF_SECS);
+                attempt += 1;
+                continue;
+            }
+
+            error!("Giving up on change event {} for webhook {} after {} attempts: {}",
+                event.id, webhook.url(), attempt, error);
+            if let Some(file) = &dead_letters {
+                let dead_letter = DeadLetter {
+                    at: unix_time(std::time::SystemTime::now()),
+                    url: webhook.url(),
+                    attempts: attempt,
+                    error: &error,
+                    event: &event,
+                };
+                let mut line = serde_json::to_vec(&dead_letter).unwrap();
+                line.push(b'\n');
+                if let Err(e) = file.lock().unwrap().write_all(&line) {
+                    error!("Failed to write change event {} to the dead letter log: {}", event.id, e);
+                }
+            }
+            break;
+        }
+    }
+}
+
+/// Forward every change event to the webhooks of its endpoint. Each webhook has its own
+/// queue, so a slow or failing receiver does not hold up the others.
+pub async fn run_webhooks(config: Arc<Config>, mut events: Receiver<ChangeEvent>, dead_letters: Option<File>) {
+    let dead_letters = dead_letters.map(|file| Arc::new(Mutex::new(file)));
+
+    let mut queues: HashMap<String, Vec<mpsc::UnboundedSender<ChangeEvent>>> = HashMap::new();
+    for endpoint in config.endpoints() {
+        for webhook in endpoint.webhooks() {
+            info!("Sending change events of {} to webhook {}", endpoint.path(), webhook.url());
+            let (sender, queue) = mpsc::unbounded_channel();
+            tokio::spawn(run_webhook(webhook.clone(), queue, dead_letters.clone()));
+            queues.entry(endpoint.path().clone()).or_default().push(sender);
+        }
+    }
+    if queues.is_empty() {
+        return;
+    }
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                error!("Webhook dispatch fell behind, {} change events were not delivered", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        for queue in queues.get(&event.endpoint).into_iter().flatten() {
+            let _ = queue.send(event.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Bytes, extract::State, http::{HeaderMap, StatusCode}, routing::post};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::broadcast;
+
+    const SECRET: &str = "a_long_webhook_secret";
+
+    #[test]
+    fn test_sign() {
+        // HMAC-SHA256 test vector from RFC 4231, test case 2
+        assert_eq!(sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    /// HookReceiver that fails the first `failures` requests and records the rest
+    #[derive(Clone, Default)]
+    struct HookReceiver {
+        failures: usize,
+        requests: Arc<AtomicUsize>,
+        delivered: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+    }
+
+    async fn receive(State(receiver): State<HookReceiver>, headers: HeaderMap, body: Bytes) -> StatusCode {
+        if receiver.requests.fetch_add(1, Ordering::SeqCst) < receiver.failures {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        receiver.delivered.lock().unwrap().push((headers, body));
+        StatusCode::NO_CONTENT
+    }
+
+    async fn start_receiver(receiver: HookReceiver) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/hook", post(receive)).with_state(receiver);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/hook", addr)
+    }
+
+    fn webhook(url: &str, max_attempts: u32) -> WebhookConfig {
+        serde_yaml::from_str(&format!(
+            "url: \"{}\"\nsecret: \"{}\"\nmax_attempts: {}\nretry_backoff_secs: 0", url, SECRET, max_attempts)).unwrap()
+    }
+
+    fn event(id: u64) -> ChangeEvent {
+        ChangeEvent {
+            id,
+            at: 1760000000,
+            endpoint: "/group_members".to_string(),
+            name: "staff".to_string(),
+            version: 1760000000007,
+            added: vec!["carol".to_string()],
+            removed: vec!["bob".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delivery_with_retries() {
+        let receiver = HookReceiver { failures: 2, ..Default::default() };
+        let url = start_receiver(receiver.clone()).await;
+
+        let (sender, queue) = mpsc::unbounded_channel();
+        sender.send(event(7)).unwrap();
+        drop(sender);
+        run_webhook(webhook(&url, 3), queue, None).await;
+
+        assert_eq!(receiver.requests.load(Ordering::SeqCst), 3);
+        let delivered = receiver.delivered.lock().unwrap();
+        let (headers, body) = &delivered[0];
+        assert_eq!(headers[SIGNATURE_HEADER], sign(SECRET, body));
+        assert_eq!(headers[EVENT_ID_HEADER], "7");
+        let payload: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["added"], serde_json::json!(["carol"]));
+        assert_eq!(payload["removed"], serde_json::json!(["bob"]));
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter() {
+        let receiver = HookReceiver { failures: usize::MAX, ..Default::default() };
+        let url = start_receiver(receiver.clone()).await;
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("dead_letters.log");
+        let dead_letters = crate::events::open_log_file(path.to_str().unwrap(), "dead letter log").unwrap();
+
+        let (sender, queue) = mpsc::unbounded_channel();
+        sender.send(event(8)).unwrap();
+        drop(sender);
+        run_webhook(webhook(&url, 2), queue, Some(Arc::new(Mutex::new(dead_letters)))).await;
+
+        assert_eq!(receiver.requests.load(Ordering::SeqCst), 2);
+        let line: serde_json::Value = serde_json::from_str(std::fs::read_to_string(&path).unwrap().trim()).unwrap();
+        assert_eq!(line["url"], url.as_str());
+        assert_eq!(line["attempts"], 2);
+        assert_eq!(line["event"]["id"], 8);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_by_endpoint() {
+        let receiver = HookReceiver::default();
+        let url = start_receiver(receiver.clone()).await;
+        let config: Config = serde_yaml::from_str(&format!(r#"
+ldap:
+  url: "ldap://localhost:389"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={{}})"
+    search_scope: "subtree"
+    attribute: "member"
+    webhooks:
+      - url: "{}"
+        secret: "{}"
+  - path: "/user_maildrop"
+    search_base: "ou=users,dc=example,dc=com"
+    search_filter: "(uid={{}})"
+    search_scope: "subtree"
+    attribute: "maildrop"
+"#, url, SECRET)).unwrap();
+
+        let (events, subscriber) = broadcast::channel(16);
+        let dispatcher = tokio::spawn(run_webhooks(Arc::new(config), subscriber, None));
+        events.send(ChangeEvent { endpoint: "/user_maildrop".to_string(), ..event(1) }).unwrap();
+        events.send(event(2)).unwrap();
+        drop(events);
+        dispatcher.await.unwrap();
+
+        for _ in 0..100 {
+            if !receiver.delivered.lock().unwrap().is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        let delivered = receiver.delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].0[EVENT_ID_HEADER], "2");
+    }
+
+    #[tokio::test]
+    async fn test_cache_fill_is_not_delivered() {
+        use crate::{cache::{Cache, CacheEntry, cache_key}, events::EventLog};
+
+        let receiver = HookReceiver::default();
+        let url = start_receiver(receiver.clone()).await;
+        let config: Config = serde_yaml::from_str(&format!(r#"
+ldap:
+  url: "ldap://localhost:389"
+  bind_dn: "cn=admin,dc=example,dc=com"
+  bind_password: "secret"
+server:
+  bind_addr: "127.0.0.1:8080"
+  refresh_interval_secs: 180
+endpoints:
+  - path: "/group_members"
+    search_base: "ou=groups,dc=example,dc=com"
+    search_filter: "(cn={{}})"
+    search_scope: "subtree"
+    attribute: "member"
+    webhooks:
+      - url: "{}"
+        secret: "{}"
+"#, url, SECRET)).unwrap();
+
+        let events = Arc::new(EventLog::new(10, None));
+        let recorder = events.clone();
+        let cache = Cache::with_recorder(move |change| {
+            recorder.record(change);
+        });
+        tokio::spawn(run_webhooks(Arc::new(config), events.subscribe(), None));
+
+        // A miss followed by a refresh returning the same values changes nothing
+        let key = cache_key("/group_members", "staff");
+        cache.insert(key.clone(), CacheEntry::new(vec!["alice".to_string()], vec![]));
+        cache.insert(key.clone(), CacheEntry::new(vec!["alice".to_string()], vec![]));
+        assert!(events.recent(0, None, 10).is_empty());
+
+        // Deliveries are made in order, so once the change arrives nothing else is coming
+        cache.insert(key, CacheEntry::new(vec!["alice".to_string(), "bob".to_string()], vec![]));
+        for _ in 0..100 {
+            if !receiver.delivered.lock().unwrap().is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        let delivered = receiver.delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 1);
+        let payload: serde_json::Value = serde_json::from_slice(&delivered[0].1).unwrap();
+        assert_eq!(payload["added"], serde_json::json!(["bob"]));
+        assert_eq!(receiver.requests.load(Ordering::SeqCst), 1);
+    }
+}